@@ -0,0 +1,119 @@
+// Execute several commands under a single elevation (Linux and MacOS)
+//
+//     cargo run --example test_batch
+#[cfg(target_family = "unix")]
+use elevated_command::{Batch, Coalescer, Command, CommandEvent, Transaction};
+#[cfg(target_family = "unix")]
+use std::process::Command as StdCommand;
+#[cfg(target_family = "unix")]
+use std::thread;
+#[cfg(target_family = "unix")]
+use std::time::Duration;
+
+#[cfg(not(target_family = "unix"))]
+fn main() {
+    println!("Batches are only supported on Linux and MacOS");
+}
+
+#[cfg(target_family = "unix")]
+fn main() {
+    println!("=== Testing batches of elevated commands ===\n");
+
+    let mut failures = 0;
+
+    println!("Test 1: Stop on the first failure");
+    let batch = Batch::new(steps(&["echo one", "echo two >&2; exit 3", "echo three"]));
+    let outputs = batch.output().unwrap();
+    failures += check(
+        outputs.len() == 3
+            && outputs[0].as_ref().is_some_and(|o| o.status.success() && o.stdout == b"one\n")
+            && outputs[1].as_ref().is_some_and(|o| o.status.code() == Some(3) && o.stderr == b"two\n")
+            && outputs[2].is_none(),
+    );
+
+    println!("\nTest 2: Continue after a failure");
+    let mut batch = Batch::new(steps(&["exit 1", "echo three"]));
+    batch.stop_on_failure(false);
+    let outputs = batch.output().unwrap();
+    failures += check(outputs[1].as_ref().is_some_and(|o| o.stdout == b"three\n"));
+
+    println!("\nTest 3: Streaming tagged with the step");
+    let batch = Batch::new(steps(&["echo one; sleep 1; echo two", "echo three"]));
+    let (rx, _child) = batch.spawn().unwrap();
+    let mut transcript = vec![];
+    while let Ok(event) = rx.recv() {
+        println!("  [{}] {:?}", event.step, event.event);
+        match event.event {
+            CommandEvent::Stdout(data) => transcript.push((event.step, String::from_utf8_lossy(&data).to_string())),
+            CommandEvent::Terminated { code, .. } => transcript.push((event.step, format!("exit {:?}", code))),
+            _ => {}
+        }
+    }
+    let text: String = transcript.iter().map(|(step, s)| format!("{}:{}", step, s)).collect();
+    failures += check(text == "0:one\n0:two\n0:exit Some(0)1:three\n1:exit Some(0)");
+
+    println!("\nTest 4: Coalesced requests");
+    let coalescer = Coalescer::new(Duration::from_millis(200));
+    let handles: Vec<_> = ["echo one", "exit 2"]
+        .into_iter()
+        .map(|script| {
+            let coalescer = coalescer.clone();
+            thread::spawn(move || coalescer.output(steps(&[script]).remove(0)))
+        })
+        .collect();
+    let outputs: Vec<_> = handles.into_iter().map(|h| h.join().unwrap().unwrap()).collect();
+    failures += check(outputs[0].stdout == b"one\n" && outputs[1].status.code() == Some(2));
+
+    println!("\nTest 5: Transaction rolled back in reverse order");
+    let dir = std::env::temp_dir().join(format!("elevated_cmd_test_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut cmds = steps(&["mkdir a", "rmdir a", "mkdir b", "rmdir b", "echo c", "exit 4"]);
+    for cmd in cmds.iter_mut() {
+        cmd.get_mut().current_dir(&dir);
+    }
+    std::fs::create_dir(&dir).unwrap();
+    let mut cmds = cmds.into_iter();
+    let mut transaction = Transaction::new();
+    transaction.step_with_rollback(cmds.next().unwrap(), cmds.next().unwrap());
+    transaction.step_with_rollback(cmds.next().unwrap(), cmds.next().unwrap());
+    transaction.step(cmds.next().unwrap());
+    transaction.step(cmds.next().unwrap());
+    let report = transaction.run().unwrap();
+    println!("  applied {:?}, rolled back {:?}", report.applied(), report.rolled_back());
+    failures += check(
+        report.failed == Some(3)
+            && report.rolled_back() == vec![1, 0]
+            && report.applied() == vec![2]
+            && std::fs::read_dir(&dir).unwrap().next().is_none(),
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+
+    if failures > 0 {
+        println!("\n✗ {} check(s) failed", failures);
+        std::process::exit(1);
+    }
+    println!("\n✓ All checks passed");
+}
+
+#[cfg(target_family = "unix")]
+fn steps(scripts: &[&str]) -> Vec<Command> {
+    scripts
+        .iter()
+        .map(|script| {
+            let mut cmd = StdCommand::new("sh");
+            cmd.arg("-c").arg(script);
+            Command::new(cmd)
+        })
+        .collect()
+}
+
+#[cfg(target_family = "unix")]
+fn check(ok: bool) -> usize {
+    if ok {
+        println!("  ✓ passed");
+        0
+    } else {
+        println!("  ✗ failed");
+        1
+    }
+}
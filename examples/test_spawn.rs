@@ -1,5 +1,5 @@
 use std::process::Command as StdCommand;
-use elevated_command::{Command, CommandEvent};
+use elevated_command::{Backpressure, Command, CommandEvent, Event};
 // use std::array::repeat;
 
 fn main() {
@@ -20,6 +20,16 @@ fn main() {
     // Example 3: App-loader simulation
     println!("Test 3: App-loader simulation with streaming");
     test_spawn_app_loader();
+
+    // Example 4: Interleaved stdout and stderr put back in order
+    println!("Test 4: Timed events merged into a transcript");
+    test_spawn_timed();
+
+    // Example 5: Bounded event queue with a slow consumer
+    println!("Test 5: Backpressure policies with a slow consumer");
+    for policy in [Backpressure::Block, Backpressure::DropOldest, Backpressure::Coalesce] {
+        test_spawn_bounded(policy);
+    }
 }
 
 fn test_spawn_simple() {
@@ -55,7 +65,7 @@ fn test_spawn_simple() {
                         let text = String::from_utf8_lossy(&data);
                         eprint!("  [stderr] {}", text);
                     }
-                    CommandEvent::Terminated { code } => {
+                    CommandEvent::Terminated { code, .. } => {
                         println!("\n✓ Process terminated with exit code: {:?}", code);
                         break;
                     }
@@ -63,6 +73,11 @@ fn test_spawn_simple() {
                         eprintln!("\n✗ Error: {}", err);
                         break;
                     }
+                    CommandEvent::Started { pid } => println!("  [started] pid {}", pid),
+                    CommandEvent::AuthRequested => println!("  [auth] waiting for authentication"),
+                    CommandEvent::AuthGranted => println!("  [auth] granted"),
+                    CommandEvent::AuthDenied => println!("  [auth] denied"),
+                    _ => {}
                 }
             }
         }
@@ -107,7 +122,7 @@ fn test_spawn_continuous() {
                         let text = String::from_utf8_lossy(&data);
                         eprint!("  [ERR] {}", text);
                     }
-                    CommandEvent::Terminated { code } => {
+                    CommandEvent::Terminated { code, .. } => {
                         println!("\n✓ Received {} lines of output", line_count);
                         println!("✓ Process terminated with code: {:?}", code);
                         break;
@@ -116,6 +131,7 @@ fn test_spawn_continuous() {
                         eprintln!("\n✗ Error: {}", err);
                         break;
                     }
+                    _ => {}
                 }
             }
         }
@@ -186,7 +202,7 @@ fn test_spawn_app_loader() {
                         let text = String::from_utf8_lossy(&data);
                         eprint!("ERROR: {}", text);
                     }
-                    CommandEvent::Terminated { code } => {
+                    CommandEvent::Terminated { code, .. } => {
                         println!("\n✓ App-loader finished with code: {:?}", code);
                         break;
                     }
@@ -194,6 +210,7 @@ fn test_spawn_app_loader() {
                         eprintln!("\n✗ App-loader error: {}", err);
                         break;
                     }
+                    _ => {}
                 }
             }
         }
@@ -202,3 +219,51 @@ fn test_spawn_app_loader() {
         }
     }
 }
+
+fn test_spawn_timed() {
+    let mut cmd = StdCommand::new("bash");
+    cmd.arg("-c").arg("for i in 1 2 3; do echo out $i; echo err $i >&2; sleep 0.2; done");
+
+    match Command::new(cmd).spawn_timed() {
+        Ok((rx, _child)) => {
+            let events: Vec<Event> = rx.iter().collect();
+            let start = events.first().map(|e| e.at);
+            for event in Event::merge([events]) {
+                let elapsed = start.map(|s| event.at - s).unwrap_or_default();
+                println!("  #{:<3} {:>8.3?} {:?}", event.seq, elapsed, event.kind);
+            }
+        }
+        Err(e) => {
+            eprintln!("✗ Failed to spawn timed command: {:?}", e);
+        }
+    }
+}
+
+fn test_spawn_bounded(policy: Backpressure) {
+    let mut cmd = StdCommand::new("bash");
+    cmd.arg("-c").arg("head -c 4000000 /dev/zero");
+
+    let mut elevated_cmd = Command::new(cmd);
+    elevated_cmd.event_capacity(8, policy);
+    match elevated_cmd.spawn() {
+        Ok((rx, _child)) => {
+            let (mut events, mut received) = (0, 0);
+            for event in rx {
+                match event {
+                    CommandEvent::Stdout(data) => {
+                        events += 1;
+                        received += data.len();
+                        std::thread::sleep(std::time::Duration::from_micros(200));
+                    }
+                    CommandEvent::Terminated { code, dropped, .. } => {
+                        println!("  {:?}: {} bytes in {} events, {} dropped, exit {:?}", policy, received, events, dropped, code);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Err(e) => {
+            eprintln!("✗ Failed to spawn bounded command: {:?}", e);
+        }
+    }
+}
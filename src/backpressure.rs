@@ -0,0 +1,413 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Luis Liu. All rights reserved.
+ *  Licensed under the MIT License. See License in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use crate::broadcast::Broadcast;
+use crate::Command;
+use crate::CommandEvent;
+use crate::Event;
+use std::collections::VecDeque;
+use std::sync::mpsc::{channel, sync_channel, Receiver, SendError, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+
+// A coalesced chunk does not grow past this, further output waits for the consumer
+const MAX_COALESCED: usize = 1024 * 1024;
+
+/// What happens to the output of a spawned command while its event queue is full,
+/// see `Command::event_capacity`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Stop reading the output until the consumer catches up, so the elevated
+    /// process blocks once its pipe is full (default)
+    #[default]
+    Block,
+    /// Discard the oldest queued output chunk to make room, counting its bytes
+    /// in `CommandEvent::Terminated`
+    DropOldest,
+    /// Append the output to the last queued chunk of the same stream, up to 1 MiB,
+    /// then block. With `Command::spawn_timed` the chunk keeps its first stamp
+    Coalesce,
+}
+
+impl Command {
+    /// Queue at most `capacity` events of `Command::spawn` which were not received
+    /// yet, applying `policy` to the output arriving while the queue is full
+    ///
+    /// The events are queued without a limit by default. The auth, start and exit
+    /// events are never dropped nor delayed
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::{Backpressure, Command, CommandEvent};
+    /// use std::process::Command as StdCommand;
+    ///
+    /// fn main() {
+    ///     let mut cmd = StdCommand::new("journalctl");
+    ///     cmd.arg("--no-pager");
+    ///     let mut elevated_cmd = Command::new(cmd);
+    ///     elevated_cmd.event_capacity(64, Backpressure::DropOldest);
+    ///     let (rx, _child) = elevated_cmd.spawn().unwrap();
+    ///
+    ///     for event in rx {
+    ///         if let CommandEvent::Terminated { dropped, .. } = event {
+    ///             println!("{} bytes were dropped", dropped);
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn event_capacity(&mut self, capacity: usize, policy: Backpressure) -> &mut Self {
+        self.event_capacity = Some((capacity.max(1), policy));
+        self
+    }
+}
+
+// The channel a spawned command reports its events through, bounded if a capacity is set
+pub(crate) fn event_channel(
+    capacity: Option<(usize, Backpressure)>,
+    replay: usize,
+) -> (EventSender, Receiver<CommandEvent>) {
+    let broadcast = Arc::new(Broadcast::new(replay));
+    let (target, rx) = match capacity {
+        Some((capacity, policy)) => {
+            let (queue, rx) = bounded(capacity, policy, |event| event.kind);
+            (Target::Bounded(queue), rx)
+        }
+        None => {
+            let (tx, rx) = channel();
+            (Target::Unbounded(tx), rx)
+        }
+    };
+    (EventSender { target, broadcast }, rx)
+}
+
+// Like `event_channel`, delivering the events with the stamp they got when sent
+pub(crate) fn timed_channel(
+    capacity: Option<(usize, Backpressure)>,
+    replay: usize,
+) -> (EventSender, Receiver<Event>) {
+    let broadcast = Arc::new(Broadcast::new(replay));
+    let (target, rx) = match capacity {
+        Some((capacity, policy)) => {
+            let (queue, rx) = bounded(capacity, policy, |event| event);
+            (Target::Bounded(queue), rx)
+        }
+        None => {
+            let (tx, rx) = channel();
+            (Target::Timed(tx), rx)
+        }
+    };
+    (EventSender { target, broadcast }, rx)
+}
+
+// A queue of at most `capacity` events, handed over to the receiver as `deliver` makes them
+fn bounded<T: Send + 'static>(
+    capacity: usize,
+    policy: Backpressure,
+    deliver: fn(Event) -> T,
+) -> (Arc<Queue>, Receiver<T>) {
+    let queue = Arc::new(Queue::new(capacity, policy));
+
+    // hand the queued events over one at a time, so only the queue holds them
+    let (tx, rx) = sync_channel(0);
+    let relay = queue.clone();
+    thread::spawn(move || {
+        while let Some(mut event) = relay.pop() {
+            if let CommandEvent::Terminated { ref mut dropped, .. } = event.kind {
+                *dropped = relay.lock().dropped;
+            }
+            if tx.send(deliver(event)).is_err() {
+                relay.lock().closed = true;
+                relay.changed.notify_all();
+                break;
+            }
+        }
+    });
+    (queue, rx)
+}
+
+#[cfg(target_os = "linux")]
+type EventCallback = Mutex<Box<dyn FnMut(CommandEvent) + Send>>;
+
+// The sending side of the event channel, a plain channel unless a capacity is set.
+// Events are stamped as they are sent when timed or queued, and a handler is called
+// right away by the thread sending the event instead. Every
+// event is also published to the subscribers of `CommandChild::subscribe`
+pub(crate) struct EventSender {
+    target: Target,
+    broadcast: Arc<Broadcast>,
+}
+
+enum Target {
+    Unbounded(Sender<CommandEvent>),
+    Timed(Sender<Event>),
+    Bounded(Arc<Queue>),
+    #[cfg(target_os = "linux")]
+    Handler(Arc<EventCallback>),
+}
+
+impl EventSender {
+    #[cfg(target_os = "linux")]
+    pub(crate) fn handler<F>(handler: F, replay: usize) -> Self
+    where
+        F: FnMut(CommandEvent) + Send + 'static,
+    {
+        EventSender {
+            target: Target::Handler(Arc::new(Mutex::new(Box::new(handler)))),
+            broadcast: Arc::new(Broadcast::new(replay)),
+        }
+    }
+
+    // Fails like `Sender::send` once the receiver and every subscriber are gone
+    pub(crate) fn send(&self, event: CommandEvent) -> Result<(), SendError<CommandEvent>> {
+        let subscribed = self.broadcast.publish(&event);
+        let sent = match self.target {
+            Target::Unbounded(ref tx) => tx.send(event),
+            Target::Timed(ref tx) => tx.send(Event::new(event)).map_err(|e| SendError(e.0.kind)),
+            Target::Bounded(ref queue) => queue.push(Event::new(event)),
+            #[cfg(target_os = "linux")]
+            Target::Handler(ref handler) => {
+                // the output readers call it in turn, never at the same time
+                let mut handler = handler.lock().unwrap_or_else(PoisonError::into_inner);
+                handler(event);
+                Ok(())
+            }
+        };
+        match sent {
+            Err(_) if subscribed => Ok(()),
+            sent => sent,
+        }
+    }
+
+    // The subscribers of the events, shared with the `CommandChild`
+    pub(crate) fn broadcast(&self) -> Arc<Broadcast> {
+        self.broadcast.clone()
+    }
+}
+
+impl Clone for EventSender {
+    fn clone(&self) -> Self {
+        let target = match self.target {
+            Target::Unbounded(ref tx) => Target::Unbounded(tx.clone()),
+            Target::Timed(ref tx) => Target::Timed(tx.clone()),
+            #[cfg(target_os = "linux")]
+            Target::Handler(ref handler) => Target::Handler(handler.clone()),
+            Target::Bounded(ref queue) => {
+                queue.lock().senders += 1;
+                Target::Bounded(queue.clone())
+            }
+        };
+        self.broadcast.attach();
+        EventSender {
+            target,
+            broadcast: self.broadcast.clone(),
+        }
+    }
+}
+
+impl Drop for EventSender {
+    fn drop(&mut self) {
+        if let Target::Bounded(ref queue) = self.target {
+            queue.lock().senders -= 1;
+            queue.changed.notify_all();
+        }
+        self.broadcast.detach();
+    }
+}
+
+pub(crate) struct Queue {
+    state: Mutex<State>,
+    changed: Condvar,
+    capacity: usize,
+    policy: Backpressure,
+}
+
+struct State {
+    events: VecDeque<Event>,
+    senders: usize,
+    // the receiver is gone
+    closed: bool,
+    dropped: u64,
+}
+
+impl Queue {
+    fn new(capacity: usize, policy: Backpressure) -> Self {
+        Queue {
+            state: Mutex::new(State {
+                events: VecDeque::new(),
+                senders: 1,
+                closed: false,
+                dropped: 0,
+            }),
+            changed: Condvar::new(),
+            capacity,
+            policy,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn push(&self, event: Event) -> Result<(), SendError<CommandEvent>> {
+        let mut state = self.lock();
+        loop {
+            if state.closed {
+                return Err(SendError(event.kind));
+            }
+            let full = state.events.len() >= self.capacity;
+            let data = match event.kind {
+                CommandEvent::Stdout(ref data) | CommandEvent::Stderr(ref data) if full => data,
+                _ => {
+                    state.events.push_back(event);
+                    self.changed.notify_all();
+                    return Ok(());
+                }
+            };
+            match self.policy {
+                Backpressure::Block => {}
+                Backpressure::DropOldest => {
+                    let oldest = state
+                        .events
+                        .iter()
+                        .position(|e| matches!(e.kind, CommandEvent::Stdout(_) | CommandEvent::Stderr(_)));
+                    if let Some(oldest) = oldest.and_then(|oldest| state.events.remove(oldest)) {
+                        if let CommandEvent::Stdout(old) | CommandEvent::Stderr(old) = oldest.kind {
+                            state.dropped += old.len() as u64;
+                        }
+                    }
+                    state.events.push_back(event);
+                    self.changed.notify_all();
+                    return Ok(());
+                }
+                Backpressure::Coalesce => {
+                    let merged = match (state.events.back_mut().map(|last| &mut last.kind), &event.kind) {
+                        (Some(CommandEvent::Stdout(last)), CommandEvent::Stdout(_))
+                        | (Some(CommandEvent::Stderr(last)), CommandEvent::Stderr(_))
+                            if last.len() + data.len() <= MAX_COALESCED =>
+                        {
+                            last.extend_from_slice(data);
+                            true
+                        }
+                        _ => false,
+                    };
+                    if merged {
+                        return Ok(());
+                    }
+                }
+            }
+            state = self.changed.wait(state).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    // The next event, or `None` once every sender is gone and the queue is drained
+    fn pop(&self) -> Option<Event> {
+        let mut state = self.lock();
+        loop {
+            if let Some(event) = state.events.pop_front() {
+                self.changed.notify_all();
+                return Some(event);
+            }
+            if state.senders == 0 {
+                return None;
+            }
+            state = self.changed.wait(state).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn chunk(data: &str) -> Event {
+        Event::new(CommandEvent::Stdout(data.as_bytes().to_vec()))
+    }
+
+    // The queued events, the output as text and anything else by name
+    fn queued(queue: &Queue) -> Vec<String> {
+        queue
+            .lock()
+            .events
+            .iter()
+            .map(|event| match event.kind {
+                CommandEvent::Stdout(ref data) => format!("out {}", String::from_utf8_lossy(data)),
+                CommandEvent::Stderr(ref data) => format!("err {}", String::from_utf8_lossy(data)),
+                ref kind => format!("{:?}", kind),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn block_waits_for_room() {
+        let queue = Arc::new(Queue::new(1, Backpressure::Block));
+        queue.push(chunk("a")).unwrap();
+        let pusher = queue.clone();
+        let pushed = thread::spawn(move || pusher.push(chunk("b")).unwrap());
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(queued(&queue), ["out a"]);
+
+        assert!(matches!(queue.pop().unwrap().kind, CommandEvent::Stdout(data) if data == b"a"));
+        pushed.join().unwrap();
+        assert_eq!(queued(&queue), ["out b"]);
+    }
+
+    #[test]
+    fn block_never_holds_back_other_events() {
+        let queue = Queue::new(1, Backpressure::Block);
+        queue.push(chunk("a")).unwrap();
+        queue.push(Event::new(CommandEvent::AuthGranted)).unwrap();
+        assert_eq!(queued(&queue), ["out a", "AuthGranted"]);
+    }
+
+    #[test]
+    fn drop_oldest_discards_the_oldest_chunk() {
+        let queue = Queue::new(3, Backpressure::DropOldest);
+        queue.push(Event::new(CommandEvent::Started { pid: 1 })).unwrap();
+        queue.push(chunk("aa")).unwrap();
+        queue.push(chunk("bbb")).unwrap();
+        queue.push(chunk("c")).unwrap();
+        assert_eq!(queued(&queue), ["Started { pid: 1 }", "out bbb", "out c"]);
+        assert_eq!(queue.lock().dropped, 2);
+    }
+
+    #[test]
+    fn coalesce_appends_to_the_last_chunk_of_the_stream() {
+        let queue = Arc::new(Queue::new(1, Backpressure::Coalesce));
+        queue.push(chunk("a")).unwrap();
+        queue.push(chunk("b")).unwrap();
+        assert_eq!(queued(&queue), ["out ab"]);
+
+        // the error output is not appended to the standard output, it waits instead
+        let pusher = queue.clone();
+        let pushed = thread::spawn(move || pusher.push(Event::new(CommandEvent::Stderr(b"c".to_vec()))).unwrap());
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(queued(&queue), ["out ab"]);
+        queue.pop().unwrap();
+        pushed.join().unwrap();
+        assert_eq!(queued(&queue), ["err c"]);
+    }
+
+    #[test]
+    fn coalesce_stops_at_the_limit() {
+        let queue = Arc::new(Queue::new(1, Backpressure::Coalesce));
+        queue.push(Event::new(CommandEvent::Stdout(vec![0; MAX_COALESCED]))).unwrap();
+        let pusher = queue.clone();
+        let pushed = thread::spawn(move || pusher.push(chunk("a")).unwrap());
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(queue.lock().events.len(), 1);
+        queue.pop().unwrap();
+        pushed.join().unwrap();
+        assert_eq!(queued(&queue), ["out a"]);
+    }
+
+    #[test]
+    fn push_fails_once_the_receiver_is_gone() {
+        let queue = Queue::new(1, Backpressure::Block);
+        queue.lock().closed = true;
+        assert!(queue.push(chunk("a")).is_err());
+    }
+}
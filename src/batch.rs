@@ -0,0 +1,470 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Luis Liu. All rights reserved.
+ *  Licensed under the MIT License. See License in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use crate::broadcast::Broadcast;
+use crate::install::Staging;
+use crate::Command;
+use crate::CommandChild;
+use crate::CommandEvent;
+#[cfg(target_os = "linux")]
+use crate::{pipe::Stdin, StreamMode};
+use anyhow::{bail, Result};
+use std::ffi::OsStr;
+use std::fs::{read, read_to_string, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::{Command as StdCommand, ExitStatus, Output};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Several commands executed in order under a single elevation, so the user is
+/// prompted once
+///
+/// By default the batch stops at the first command exiting unsuccessfully. The batch
+/// is shown to the user like its first command, whose authentication retries and
+/// cancellation also apply to the whole batch
+///
+/// The steps cannot read any input and their output is always captured, a step
+/// set up with a pseudo-terminal, an input or another stream mode is rejected
+///
+/// # Examples
+///
+/// ```no_run
+/// use elevated_command::{Batch, Command};
+/// use std::process::Command as StdCommand;
+///
+/// fn main() {
+///     let mut first = StdCommand::new("path to the application");
+///     first.arg("first");
+///     let mut second = StdCommand::new("path to the application");
+///     second.arg("second");
+///
+///     let mut batch = Batch::new(vec![Command::new(first), Command::new(second)]);
+///     batch.stop_on_failure(false);
+///     for (step, output) in batch.output().unwrap().iter().enumerate() {
+///         match output {
+///             Some(output) => println!("step {}: {}", step, output.status),
+///             None => println!("step {}: not executed", step),
+///         }
+///     }
+/// }
+/// ```
+pub struct Batch {
+    steps: Vec<Command>,
+    stop_on_failure: bool,
+}
+
+/// An event of one step of a `Batch`
+#[derive(Debug, Clone)]
+pub struct BatchEvent {
+    /// The index of the step in the batch
+    pub step: usize,
+    pub event: CommandEvent,
+}
+
+// The script in the staging directory, next to the files of the steps
+const SCRIPT_NAME: &str = "batch.sh";
+
+// How often the files written by the steps are checked while streaming
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+impl Batch {
+    /// Constructs a batch executing `steps` in order
+    pub fn new(steps: Vec<Command>) -> Self {
+        Self {
+            steps,
+            stop_on_failure: true,
+        }
+    }
+
+    /// Stop at the first step exiting unsuccessfully, or go on with the next ones,
+    /// stopping by default
+    pub fn stop_on_failure(&mut self, stop_on_failure: bool) -> &mut Self {
+        self.stop_on_failure = stop_on_failure;
+        self
+    }
+
+    fn script(&self) -> Result<Script> {
+        for step in self.steps.iter() {
+            Script::check(step)?;
+        }
+        let steps: Vec<_> = self.steps.iter().map(|step| &step.cmd).collect();
+        Script::new(&steps, self.stop_on_failure)
+    }
+
+    /// Execute all steps with escalated privileges, returning the output of each step,
+    /// or `None` for the steps not executed after a failure
+    pub fn output(&self) -> Result<Vec<Option<Output>>> {
+        if self.steps.is_empty() {
+            return Ok(vec![]);
+        }
+        self.script()?.run(&self.steps[0])
+    }
+
+    /// Execute all steps with escalated privileges and stream their output in real-time
+    ///
+    /// Every step ends with a `CommandEvent::Terminated` event, the channel is closed
+    /// once the batch is done
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::{Batch, Command, CommandEvent};
+    /// use std::process::Command as StdCommand;
+    ///
+    /// fn main() {
+    ///     let first = StdCommand::new("path to the application");
+    ///     let second = StdCommand::new("path to the application");
+    ///     let batch = Batch::new(vec![Command::new(first), Command::new(second)]);
+    ///
+    ///     let (rx, _child) = batch.spawn().unwrap();
+    ///     while let Ok(event) = rx.recv() {
+    ///         match event.event {
+    ///             CommandEvent::Stdout(data) => {
+    ///                 println!("{}: {}", event.step, String::from_utf8_lossy(&data));
+    ///             }
+    ///             CommandEvent::Terminated { code, .. } => {
+    ///                 println!("{}: exited with code {:?}", event.step, code);
+    ///             }
+    ///             _ => {}
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn spawn(self) -> Result<(Receiver<BatchEvent>, CommandChild)> {
+        let first = match self.steps.first() {
+            Some(first) => first,
+            // nothing to elevate, the channel is closed right away
+            None => return Ok((channel().1, no_child())),
+        };
+        let script = self.script()?;
+        let (events, child) = script.command(first).spawn()?;
+
+        let (tx, rx) = channel();
+        thread::spawn(move || monitor_steps(tx, script, events));
+        Ok((rx, child))
+    }
+}
+
+// The child of a batch without steps, no process was started
+fn no_child() -> CommandChild {
+    let broadcast = Arc::new(Broadcast::new(0));
+    broadcast.detach();
+    CommandChild {
+        _output_dir: PathBuf::new(),
+        process: None,
+        stdin: None,
+        #[cfg(target_os = "linux")]
+        pty: None,
+        broadcast,
+    }
+}
+
+// Follow the files of the steps one after the other until the elevated shell exits
+fn monitor_steps(tx: Sender<BatchEvent>, script: Script, events: Receiver<CommandEvent>) {
+    let mut shell_done = false;
+    let mut shell_stderr = vec![];
+    let mut step = 0;
+    let mut stdout = Tail::new(script.stdout_path(step));
+    let mut stderr = Tail::new(script.stderr_path(step));
+
+    while step < script.steps {
+        loop {
+            match events.try_recv() {
+                Ok(CommandEvent::Stderr(data)) => shell_stderr.extend(data),
+                Ok(CommandEvent::Terminated { .. }) | Ok(CommandEvent::Error(_)) => shell_done = true,
+                Ok(_) => {}
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    shell_done = true;
+                    break;
+                }
+            }
+        }
+
+        // the exit code is written last, read it first to not miss any output
+        let code = read_to_string(script.code_path(step))
+            .ok()
+            .filter(|code| code.ends_with('\n'));
+        let mut sent = true;
+        if let Some(data) = stdout.read_new() {
+            sent &= send(&tx, step, CommandEvent::Stdout(data));
+        }
+        if let Some(data) = stderr.read_new() {
+            sent &= send(&tx, step, CommandEvent::Stderr(data));
+        }
+        if let Some(code) = code {
+            sent &= send(&tx, step, CommandEvent::Terminated {
+                code: code.trim_end().parse().ok(),
+                signal: None,
+                dropped: 0,
+            });
+            step += 1;
+            stdout = Tail::new(script.stdout_path(step));
+            stderr = Tail::new(script.stderr_path(step));
+            if !sent {
+                break;
+            }
+            continue;
+        }
+        if !sent {
+            break;
+        }
+
+        if shell_done {
+            if step == 0 {
+                let message = format!(
+                    "failed to run the batch: {}",
+                    String::from_utf8_lossy(&shell_stderr).trim_end(),
+                );
+                send(&tx, step, CommandEvent::Error(message));
+            }
+            break;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn send(tx: &Sender<BatchEvent>, step: usize, event: CommandEvent) -> bool {
+    tx.send(BatchEvent { step, event }).is_ok()
+}
+
+// The part of a file not read yet
+struct Tail {
+    path: PathBuf,
+    pos: u64,
+}
+
+impl Tail {
+    fn new(path: PathBuf) -> Self {
+        Self { path, pos: 0 }
+    }
+
+    fn read_new(&mut self) -> Option<Vec<u8>> {
+        let mut file = File::open(&self.path).ok()?;
+        file.seek(SeekFrom::Start(self.pos)).ok()?;
+        let mut buffer = vec![];
+        file.read_to_end(&mut buffer).ok()?;
+        self.pos += buffer.len() as u64;
+        if buffer.is_empty() {
+            None
+        } else {
+            Some(buffer)
+        }
+    }
+}
+
+// Several commands executed by a single elevated shell. Every step writes its output
+// and exit code to its own files, so the results can be told apart afterwards
+pub(crate) struct Script {
+    staging: Staging,
+    steps: usize,
+}
+
+impl Script {
+    /// Write the script running `steps` in order, giving up after the first failing
+    /// one if `stop_on_failure` is set
+    pub fn new(steps: &[&StdCommand], stop_on_failure: bool) -> Result<Self> {
+        Self::create(steps, &[], stop_on_failure)
+    }
+
+    /// Write the script running `steps` in order until one fails, then the rollbacks
+    /// of the steps completed before in reverse order
+    pub fn transaction(steps: &[&StdCommand], rollbacks: &[Option<&StdCommand>]) -> Result<Self> {
+        Self::create(steps, rollbacks, true)
+    }
+
+    fn create(
+        steps: &[&StdCommand],
+        rollbacks: &[Option<&StdCommand>],
+        stop_on_failure: bool,
+    ) -> Result<Self> {
+        let script = Self {
+            staging: Staging::new()?,
+            steps: steps.len(),
+        };
+
+        let mut contents = b"exec </dev/null\n".to_vec();
+        for (i, step) in steps.iter().enumerate() {
+            contents.extend(script.step_line(&i.to_string(), step));
+            if stop_on_failure {
+                contents.extend(b"[ $status -eq 0 ] || {\nfailed=$status\n");
+                for undo in (0..i).rev() {
+                    if let Some(Some(rollback)) = rollbacks.get(undo) {
+                        contents.extend(script.step_line(&format!("r{}", undo), rollback));
+                    }
+                }
+                contents.extend(b"exit $failed\n}\n");
+            }
+        }
+        script.staging.file(SCRIPT_NAME, &contents)?;
+        Ok(script)
+    }
+
+    fn path(&self) -> PathBuf {
+        self.staging.dir().join(SCRIPT_NAME)
+    }
+
+    // the files of step N are named `N.*`, those of its rollback `rN.*`
+    fn file(&self, name: &str, extension: &str) -> PathBuf {
+        self.staging.dir().join(format!("{}.{}", name, extension))
+    }
+
+    pub fn stdout_path(&self, step: usize) -> PathBuf {
+        self.file(&step.to_string(), "out")
+    }
+
+    pub fn stderr_path(&self, step: usize) -> PathBuf {
+        self.file(&step.to_string(), "err")
+    }
+
+    pub fn code_path(&self, step: usize) -> PathBuf {
+        self.file(&step.to_string(), "code")
+    }
+
+    // ( cd DIR && exec env -u REMOVED KEY=VALUE PROGRAM ARGS ) >NAME.out 2>NAME.err
+    fn step_line(&self, name: &str, cmd: &StdCommand) -> Vec<u8> {
+        let mut line = b"(".to_vec();
+        if let Some(dir) = cmd.get_current_dir() {
+            line.extend(b" cd ");
+            line.extend(quote(dir.as_os_str()));
+            line.extend(b" &&");
+        }
+        line.extend(b" exec env");
+        for (k, v) in cmd.get_envs() {
+            match v {
+                Some(value) => {
+                    let mut pair = k.to_os_string();
+                    pair.push("=");
+                    pair.push(value);
+                    line.push(b' ');
+                    line.extend(quote(&pair));
+                }
+                None => {
+                    line.extend(b" -u ");
+                    line.extend(quote(k));
+                }
+            }
+        }
+        line.push(b' ');
+        line.extend(quote(cmd.get_program()));
+        for arg in cmd.get_args() {
+            line.push(b' ');
+            line.extend(quote(arg));
+        }
+        line.extend(b" ) >");
+        line.extend(quote(self.file(name, "out").as_os_str()));
+        line.extend(b" 2>");
+        line.extend(quote(self.file(name, "err").as_os_str()));
+        line.extend(b"\nstatus=$?\necho $status >");
+        line.extend(quote(self.file(name, "code").as_os_str()));
+        line.push(b'\n');
+        line
+    }
+
+    /// Fail if `step` asks for settings a step of the script cannot honour: its input
+    /// is closed and its output is written to files
+    pub fn check(step: &Command) -> Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            if step.pty {
+                bail!("a pseudo-terminal is not supported in a batch");
+            }
+            if !matches!(step.stdin, Stdin::Null) {
+                bail!("the input of a step is not supported in a batch");
+            }
+            if step.stdout != StreamMode::Capture || step.stderr != StreamMode::Capture {
+                bail!("only captured output is supported in a batch");
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = step;
+        Ok(())
+    }
+
+    /// The elevated shell executing the script, shown to the user like `first` and
+    /// authorized under its polkit action, details, helper and cancellation
+    pub fn command(&self, first: &Command) -> Command {
+        let mut cmd = StdCommand::new("/bin/sh");
+        cmd.arg(self.path());
+        let mut elevated = Command::new(cmd);
+        elevated.icon = first.icon.clone();
+        elevated.name = first.name.clone();
+        elevated.non_interactive = first.non_interactive;
+        #[cfg(target_os = "linux")]
+        {
+            elevated.action_id = first.action_id.clone();
+            elevated.details = first.details.clone();
+            elevated.helper = first.helper.clone();
+        }
+        #[cfg(all(target_os = "linux", feature = "polkit-dbus"))]
+        {
+            elevated.cancellation = first.cancellation.clone();
+        }
+        elevated
+    }
+
+    fn output_of(&self, name: &str) -> Result<Option<Output>> {
+        let code = match read_to_string(self.file(name, "code")) {
+            Ok(code) => code,
+            Err(_) => return Ok(None),
+        };
+        let code: i32 = code.trim_end().parse()?;
+        Ok(Some(Output {
+            status: ExitStatus::from_raw(code << 8),
+            stdout: read(self.file(name, "out")).unwrap_or_default(),
+            stderr: read(self.file(name, "err")).unwrap_or_default(),
+        }))
+    }
+
+    /// The output of a step, `None` if it did not run
+    pub fn step_output(&self, step: usize) -> Result<Option<Output>> {
+        self.output_of(&step.to_string())
+    }
+
+    /// The output of the rollback of a step, `None` if it did not run
+    pub fn rollback_output(&self, step: usize) -> Result<Option<Output>> {
+        self.output_of(&format!("r{}", step))
+    }
+
+    /// Run the script elevated, failing if the shell itself could not be elevated
+    ///
+    /// A failed authentication is retried as set for `first`
+    pub fn execute(&self, first: &Command) -> Result<()> {
+        let shell = self.command(first);
+        let output = first.retry_auth(|| shell.output())?;
+        if self.steps > 0 && self.step_output(0)?.is_none() {
+            bail!(
+                "failed to run the batch: {}",
+                String::from_utf8_lossy(&output.stderr).trim_end(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Run the script elevated and collect the output of every step
+    pub fn run(&self, first: &Command) -> Result<Vec<Option<Output>>> {
+        self.execute(first)?;
+        (0..self.steps).map(|step| self.step_output(step)).collect()
+    }
+}
+
+// Quote for the shell, a single quote is written as '\''
+fn quote(s: &OsStr) -> Vec<u8> {
+    let mut quoted = b"'".to_vec();
+    for &b in s.as_bytes() {
+        if b == b'\'' {
+            quoted.extend(b"'\\''");
+        } else {
+            quoted.push(b);
+        }
+    }
+    quoted.push(b'\'');
+    quoted
+}
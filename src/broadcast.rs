@@ -0,0 +1,150 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Luis Liu. All rights reserved.
+ *  Licensed under the MIT License. See License in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use crate::Command;
+use crate::CommandChild;
+use crate::CommandEvent;
+use std::collections::VecDeque;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+// The subscribers of a spawned command, with the latest events replayed to new ones
+pub(crate) struct Broadcast {
+    state: Mutex<State>,
+}
+
+struct State {
+    history: VecDeque<CommandEvent>,
+    replay: usize,
+    subscribers: Vec<Sender<CommandEvent>>,
+    // the event senders still alive, no event is published once they are all gone
+    senders: usize,
+}
+
+impl Broadcast {
+    pub(crate) fn new(replay: usize) -> Self {
+        Self {
+            state: Mutex::new(State {
+                history: VecDeque::with_capacity(replay),
+                replay,
+                subscribers: vec![],
+                senders: 1,
+            }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn attach(&self) {
+        self.lock().senders += 1;
+    }
+
+    // Once the last sender is gone, the subscribers are disconnected
+    pub(crate) fn detach(&self) {
+        let mut state = self.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            state.subscribers.clear();
+        }
+    }
+
+    // Returns whether anyone is still subscribed
+    pub(crate) fn publish(&self, event: &CommandEvent) -> bool {
+        let mut state = self.lock();
+        if state.replay > 0 {
+            if state.history.len() == state.replay {
+                state.history.pop_front();
+            }
+            state.history.push_back(event.clone());
+        }
+        state.subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+        !state.subscribers.is_empty()
+    }
+
+    fn subscribe(&self) -> Receiver<CommandEvent> {
+        let mut state = self.lock();
+        let (tx, rx) = channel();
+        for event in state.history.iter() {
+            let _ = tx.send(event.clone());
+        }
+        if state.senders > 0 {
+            state.subscribers.push(tx);
+        }
+        rx
+    }
+}
+
+impl Command {
+    /// Keep the last `events` events of `Command::spawn`, so a receiver from
+    /// `CommandChild::subscribe` gets them before the live events
+    ///
+    /// Nothing is kept by default, a subscriber only gets the events which happen
+    /// after it subscribed
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::Command;
+    /// use std::process::Command as StdCommand;
+    ///
+    /// fn main() {
+    ///     let cmd = StdCommand::new("path to the application");
+    ///     let mut elevated_cmd = Command::new(cmd);
+    ///     elevated_cmd.replay_buffer(1000);
+    ///     let (_rx, child) = elevated_cmd.spawn().unwrap();
+    ///
+    ///     // a log panel opened later still shows the beginning of the output
+    ///     let panel = child.subscribe();
+    /// }
+    /// ```
+    pub fn replay_buffer(&mut self, events: usize) -> &mut Self {
+        self.replay_buffer = events;
+        self
+    }
+}
+
+impl CommandChild {
+    /// Receive the events of the command independently of the receiver returned by
+    /// `Command::spawn`
+    ///
+    /// The receiver first gets the events kept by `Command::replay_buffer`, then every
+    /// new event, and is disconnected once the command is done. Events are queued for
+    /// each subscriber without a limit, so a receiver should be read or dropped
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::{Command, CommandEvent};
+    /// use std::process::Command as StdCommand;
+    /// use std::thread;
+    ///
+    /// fn main() {
+    ///     let cmd = StdCommand::new("path to the application");
+    ///     let elevated_cmd = Command::new(cmd);
+    ///     let (rx, child) = elevated_cmd.spawn().unwrap();
+    ///
+    ///     let log = child.subscribe();
+    ///     let logger = thread::spawn(move || {
+    ///         for event in log {
+    ///             if let CommandEvent::Stderr(data) = event {
+    ///                 eprint!("{}", String::from_utf8_lossy(&data));
+    ///             }
+    ///         }
+    ///     });
+    ///
+    ///     for event in rx {
+    ///         if let CommandEvent::Stdout(data) = event {
+    ///             print!("{}", String::from_utf8_lossy(&data));
+    ///         }
+    ///     }
+    ///     logger.join().unwrap();
+    /// }
+    /// ```
+    pub fn subscribe(&self) -> Receiver<CommandEvent> {
+        self.broadcast.subscribe()
+    }
+}
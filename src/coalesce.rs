@@ -0,0 +1,212 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Luis Liu. All rights reserved.
+ *  Licensed under the MIT License. See License in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use crate::batch::Script;
+use crate::Command;
+use crate::ElevationError;
+use anyhow::{anyhow, Result};
+#[cfg(target_os = "linux")]
+use std::path::PathBuf;
+use std::process::Output;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Collects the elevation requests made within a short interval and executes them
+/// under a single elevation, so the user is prompted once
+///
+/// A batch is executed once no new request was made for the debounce interval.
+/// Every caller gets the output of its own command back, a failing command does not
+/// affect the others. Only commands with the same settings, like the name, icon and
+/// authentication retries, are executed together, the batch being shown to the user
+/// like its first command. A command a batch cannot honour, like one reading an input
+/// or with its own authentication failure handler, is executed under its own elevation
+///
+/// # Examples
+///
+/// ```no_run
+/// use elevated_command::{Coalescer, Command};
+/// use std::process::Command as StdCommand;
+/// use std::thread;
+/// use std::time::Duration;
+///
+/// fn main() {
+///     let coalescer = Coalescer::new(Duration::from_millis(200));
+///     let handles: Vec<_> = ["first", "second"]
+///         .into_iter()
+///         .map(|name| {
+///             let coalescer = coalescer.clone();
+///             thread::spawn(move || {
+///                 let mut cmd = StdCommand::new("path to the application");
+///                 cmd.arg(name);
+///                 coalescer.output(Command::new(cmd))
+///             })
+///         })
+///         .collect();
+///     for handle in handles {
+///         let output = handle.join().unwrap().unwrap();
+///         println!("{}", output.status);
+///     }
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Coalescer {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    debounce: Duration,
+    pending: Mutex<Pending>,
+}
+
+struct Pending {
+    requests: Vec<(Command, Sender<Result<Output>>)>,
+    last: Instant,
+    scheduled: bool,
+}
+
+impl Coalescer {
+    /// Constructs a coalescer waiting for `debounce` without new requests before
+    /// executing them
+    pub fn new(debounce: Duration) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                debounce,
+                pending: Mutex::new(Pending {
+                    requests: vec![],
+                    last: Instant::now(),
+                    scheduled: false,
+                }),
+            }),
+        }
+    }
+
+    /// Queue `cmd` with the other pending requests and wait for its output
+    pub fn output(&self, cmd: Command) -> Result<Output> {
+        let (tx, rx) = channel();
+        {
+            let mut pending = self.inner.pending();
+            pending.requests.push((cmd, tx));
+            pending.last = Instant::now();
+            if !pending.scheduled {
+                pending.scheduled = true;
+                let inner = self.inner.clone();
+                thread::spawn(move || inner.run());
+            }
+        }
+        rx.recv().map_err(|_| anyhow!("the batch was aborted"))?
+    }
+}
+
+impl Inner {
+    fn pending(&self) -> MutexGuard<'_, Pending> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Wait until no request was made for the debounce interval, then execute them all
+    fn run(&self) {
+        let requests = loop {
+            let wait = {
+                let mut pending = self.pending();
+                let idle = pending.last.elapsed();
+                if idle >= self.debounce {
+                    pending.scheduled = false;
+                    break std::mem::take(&mut pending.requests);
+                }
+                self.debounce - idle
+            };
+            thread::sleep(wait);
+        };
+
+        let (commands, replies): (Vec<_>, Vec<_>) = requests.into_iter().unzip();
+        let keys: Vec<_> = commands.iter().map(Settings::of).collect();
+        let mut groups: Vec<Vec<usize>> = vec![];
+        for (i, key) in keys.iter().enumerate() {
+            let group = key
+                .as_ref()
+                .and_then(|key| groups.iter_mut().find(|group| keys[group[0]].as_ref() == Some(key)));
+            match group {
+                Some(group) => group.push(i),
+                None => groups.push(vec![i]),
+            }
+        }
+        for group in groups {
+            let commands: Vec<_> = group.iter().map(|&i| &commands[i]).collect();
+            let replies: Vec<_> = group.iter().map(|&i| &replies[i]).collect();
+            run_batch(&commands, &replies);
+        }
+    }
+}
+
+// Execute the commands under a single elevation and send every caller its output
+fn run_batch(commands: &[&Command], replies: &[&Sender<Result<Output>>]) {
+    if let [cmd] = commands {
+        let _ = replies[0].send(cmd.output());
+        return;
+    }
+
+    let steps: Vec<_> = commands.iter().map(|cmd| &cmd.cmd).collect();
+    let outputs = Script::new(&steps, false).and_then(|script| script.run(commands[0]));
+    match outputs {
+        Ok(outputs) => {
+            for (output, reply) in outputs.into_iter().zip(replies) {
+                let _ = reply.send(output.ok_or(anyhow!("the command was not executed")));
+            }
+        }
+        Err(e) => {
+            // every caller gets its own copy of the error
+            for reply in replies {
+                let err = match e.downcast_ref::<ElevationError>() {
+                    Some(err) => err.clone().into(),
+                    None => anyhow!("{:#}", e),
+                };
+                let _ = reply.send(Err(err));
+            }
+        }
+    }
+}
+
+// The settings a batch takes from its first command, only commands with the same
+// ones are executed together
+#[derive(PartialEq)]
+struct Settings<'a> {
+    icon: &'a Option<Vec<u8>>,
+    name: &'a Option<String>,
+    non_interactive: bool,
+    auth_retries: usize,
+    #[cfg(target_os = "linux")]
+    action_id: &'a Option<String>,
+    #[cfg(target_os = "linux")]
+    details: &'a [(String, String)],
+    #[cfg(target_os = "linux")]
+    helper: &'a Option<PathBuf>,
+    #[cfg(all(target_os = "linux", feature = "polkit-dbus"))]
+    cancellation: Option<&'a str>,
+}
+
+impl<'a> Settings<'a> {
+    // `None` for a command executed on its own: a batch cannot honour its settings,
+    // or it has an authentication failure handler of its own
+    fn of(cmd: &'a Command) -> Option<Self> {
+        if Script::check(cmd).is_err() || cmd.on_auth_failure.is_some() {
+            return None;
+        }
+        Some(Self {
+            icon: &cmd.icon,
+            name: &cmd.name,
+            non_interactive: cmd.non_interactive,
+            auth_retries: cmd.auth_retries,
+            #[cfg(target_os = "linux")]
+            action_id: &cmd.action_id,
+            #[cfg(target_os = "linux")]
+            details: &cmd.details,
+            #[cfg(target_os = "linux")]
+            helper: &cmd.helper,
+            #[cfg(all(target_os = "linux", feature = "polkit-dbus"))]
+            cancellation: cmd.cancellation.as_ref().map(|c| c.id()),
+        })
+    }
+}
@@ -0,0 +1,153 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Luis Liu. All rights reserved.
+ *  Licensed under the MIT License. See License in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+// Talk to the polkit authority over the system bus
+// See https://www.freedesktop.org/software/polkit/docs/latest/eggdbus-interface-org.freedesktop.PolicyKit1.Authority.html
+
+use crate::linux::process_start_time;
+use crate::ElevationError;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use zbus::blocking::{Connection, Proxy};
+use zbus::zvariant::Value;
+
+const DESTINATION: &str = "org.freedesktop.PolicyKit1";
+const PATH: &str = "/org/freedesktop/PolicyKit1/Authority";
+const INTERFACE: &str = "org.freedesktop.PolicyKit1.Authority";
+const ERROR_CANCELLED: &str = "org.freedesktop.PolicyKit1.Error.Cancelled";
+const ALLOW_USER_INTERACTION: u32 = 1;
+// The implicit authorizations which let an authentication be reused for a while,
+// `auth_self_keep`, `auth_admin_keep` and `yes`
+const RETAINED: [u32; 3] = [3, 4, 5];
+
+// action id, description, message, vendor name, vendor url, icon name, implicit
+// authorizations for any, inactive and active sessions, annotations
+type ActionDescription = (String, String, String, String, String, String, u32, u32, u32, HashMap<String, String>);
+
+pub(crate) struct Authorization {
+    pub is_authorized: bool,
+    pub is_challenge: bool,
+    pub details: HashMap<String, String>,
+}
+
+impl Authorization {
+    pub fn is_dismissed(&self) -> bool {
+        self.details.get("polkit.dismissed").is_some_and(|v| !v.is_empty())
+    }
+}
+
+// polkit only accepts cancelling a check from the connection which started it,
+// so every call goes through the same connection
+fn proxy() -> Result<Proxy<'static>> {
+    static CONNECTION: OnceLock<Connection> = OnceLock::new();
+    let connection = match CONNECTION.get() {
+        Some(connection) => connection,
+        None => {
+            let connection = Connection::system()?;
+            CONNECTION.get_or_init(|| connection)
+        }
+    };
+    Ok(Proxy::new(connection, DESTINATION, PATH, INTERFACE)?)
+}
+
+/// Ask polkit whether the current process is authorized for `action_id`,
+/// letting the authentication agent prompt the user if `interactive` is set
+pub(crate) fn check_authorization(
+    action_id: &str,
+    details: &[(String, String)],
+    interactive: bool,
+    cancellation_id: &str,
+) -> Result<Authorization> {
+    let mut subject: HashMap<&str, Value> = HashMap::new();
+    subject.insert("pid", Value::U32(std::process::id()));
+    subject.insert("start-time", Value::U64(process_start_time()?));
+    subject.insert("uid", Value::I32(unsafe { libc::getuid() } as i32));
+    let details: HashMap<&str, &str> = details.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    let flags = if interactive { ALLOW_USER_INTERACTION } else { 0 };
+
+    let reply = proxy()?.call(
+        "CheckAuthorization",
+        &(("unix-process", subject), action_id, details, flags, cancellation_id),
+    );
+    let (is_authorized, is_challenge, details): (bool, bool, HashMap<String, String>) = match reply {
+        Ok(reply) => reply,
+        Err(zbus::Error::MethodError(name, _, _)) if name.as_str() == ERROR_CANCELLED => {
+            return Err(ElevationError::Cancelled.into());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    Ok(Authorization {
+        is_authorized,
+        is_challenge,
+        details,
+    })
+}
+
+/// Whether the policy of `action_id` lets an active session reuse an authentication,
+/// so authorizing ahead of `pkexec` does not prompt the user twice. `.rules` files
+/// granting the action are not taken into account
+pub(crate) fn retains_authorization(action_id: &str) -> Result<bool> {
+    let actions: Vec<ActionDescription> = proxy()?.call("EnumerateActions", &("",))?;
+    Ok(actions
+        .iter()
+        .find(|action| action.0 == action_id)
+        .is_some_and(|action| RETAINED.contains(&action.8)))
+}
+
+/// A handle cancelling a pending polkit authorization, dismissing its dialog
+///
+/// Only an authorization checked over D-Bus can be cancelled, see `Command::cancellation`
+///
+/// # Examples
+///
+/// ```no_run
+/// use elevated_command::{Cancellation, Command};
+/// use std::process::Command as StdCommand;
+/// use std::thread;
+///
+/// fn main() {
+///     let cmd = StdCommand::new("path to the application");
+///     let cancellation = Cancellation::new();
+///     let mut elevated_cmd = Command::new(cmd);
+///     elevated_cmd.cancellation(cancellation.clone());
+///
+///     let handle = thread::spawn(move || elevated_cmd.authorize());
+///     cancellation.cancel().unwrap();
+///     assert!(handle.join().unwrap().is_err());
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Cancellation {
+    id: String,
+}
+
+impl Cancellation {
+    /// Constructs a handle with an id unique to the current process
+    pub fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        Self {
+            id: format!("elevated-command-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)),
+        }
+    }
+
+    /// The cancellation id passed to polkit
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Cancel the authorization check started with this handle
+    pub fn cancel(&self) -> Result<()> {
+        proxy()?.call::<_, _, ()>("CancelCheckAuthorization", &(self.id.as_str(),))?;
+        Ok(())
+    }
+}
+
+impl Default for Cancellation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,73 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Luis Liu. All rights reserved.
+ *  Licensed under the MIT License. See License in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use crate::Backend;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Output};
+use std::time::Duration;
+
+/// The output of `Command::output_details`, telling how the command was elevated
+/// and how long authenticating and running it took
+///
+/// Converts into `std::process::Output` for code expecting the result of `Command::output`
+#[derive(Debug, Clone)]
+pub struct ElevatedOutput {
+    /// The exit status of the process
+    pub status: ExitStatus,
+    /// The data that the process wrote to stdout
+    pub stdout: Vec<u8>,
+    /// The data that the process wrote to stderr
+    pub stderr: Vec<u8>,
+    /// How long the user took to authenticate, zero when not prompted and `None`
+    /// when it could not be told apart from running the command
+    pub auth_wait: Option<Duration>,
+    /// How long the command ran, after authenticating when that is known
+    pub run_duration: Duration,
+    /// The backend which elevated the command
+    pub backend: Backend,
+    /// The program executed, resolved through `PATH` when possible
+    pub program: PathBuf,
+    /// The user the command was executed as
+    pub user: String,
+    /// The resources used by the elevated process
+    #[cfg(target_os = "linux")]
+    pub usage: ResourceUsage,
+}
+
+/// The resources used by a process and the children it waited for, see
+/// `ElevatedOutput::usage`
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    /// CPU time spent in user mode
+    pub user_time: Duration,
+    /// CPU time spent in the kernel
+    pub system_time: Duration,
+    /// The largest resident set size, in bytes
+    pub max_rss: u64,
+}
+
+#[cfg(target_os = "linux")]
+impl From<&libc::rusage> for ResourceUsage {
+    fn from(usage: &libc::rusage) -> Self {
+        let time = |t: libc::timeval| Duration::new(t.tv_sec as u64, t.tv_usec as u32 * 1000);
+        Self {
+            user_time: time(usage.ru_utime),
+            system_time: time(usage.ru_stime),
+            // reported in kilobytes
+            max_rss: usage.ru_maxrss as u64 * 1024,
+        }
+    }
+}
+
+impl From<ElevatedOutput> for Output {
+    fn from(output: ElevatedOutput) -> Self {
+        Output {
+            status: output.status,
+            stdout: output.stdout,
+            stderr: output.stderr,
+        }
+    }
+}
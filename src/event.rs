@@ -0,0 +1,114 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Luis Liu. All rights reserved.
+ *  Licensed under the MIT License. See License in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use crate::backpressure::timed_channel;
+use crate::Command;
+use crate::CommandChild;
+use crate::CommandEvent;
+use anyhow::Result;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::Receiver;
+use std::time::Instant;
+
+// Shared by all commands, so the events of several commands can be ordered too
+static SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// A `CommandEvent` stamped when it was received from the elevated process,
+/// see `Command::spawn_timed`
+#[derive(Debug, Clone)]
+pub struct Event {
+    /// Increases with every event of every command in the program
+    pub seq: u64,
+    /// When the event was received, from a monotonic clock
+    pub at: Instant,
+    /// The event itself
+    pub kind: CommandEvent,
+}
+
+impl Event {
+    /// Stamp `kind` with the next sequence number and the current time
+    pub fn new(kind: CommandEvent) -> Self {
+        Self {
+            seq: SEQ.fetch_add(1, Ordering::Relaxed),
+            at: Instant::now(),
+            kind,
+        }
+    }
+
+    /// Merge several streams of events into one transcript ordered by `seq`
+    ///
+    /// Every chunk stays a separate event with its own stamp, so the time between
+    /// the chunks of a stream is kept
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::{Command, CommandEvent, Event};
+    /// use std::process::Command as StdCommand;
+    ///
+    /// fn main() {
+    ///     let cmd = StdCommand::new("path to the application");
+    ///     let elevated_cmd = Command::new(cmd);
+    ///     let (rx, _child) = elevated_cmd.spawn_timed().unwrap();
+    ///
+    ///     let (stdout, stderr): (Vec<Event>, Vec<Event>) = rx
+    ///         .iter()
+    ///         .filter(|e| matches!(e.kind, CommandEvent::Stdout(_) | CommandEvent::Stderr(_)))
+    ///         .partition(|e| matches!(e.kind, CommandEvent::Stdout(_)));
+    ///     for event in Event::merge([stdout, stderr]) {
+    ///         println!("#{} {:?}", event.seq, event.kind);
+    ///     }
+    /// }
+    /// ```
+    pub fn merge<I>(streams: I) -> Vec<Event>
+    where
+        I: IntoIterator,
+        I::Item: IntoIterator<Item = Event>,
+    {
+        let mut events: Vec<Event> = streams.into_iter().flatten().collect();
+        events.sort_by_key(|event| event.seq);
+        events
+    }
+}
+
+impl Command {
+    /// Spawn the command like `Command::spawn`, stamping every event with a sequence
+    /// number and the time it was received
+    ///
+    /// The events are stamped by the threads reading the output of the process as
+    /// they send them, however late they are read from the returned receiver, so the
+    /// interleaving of the standard output and error and the latency of the process
+    /// can be recovered. `Command::event_capacity` applies to the stamped events
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::{Command, CommandEvent};
+    /// use std::process::Command as StdCommand;
+    ///
+    /// fn main() {
+    ///     let cmd = StdCommand::new("path to the application");
+    ///     let elevated_cmd = Command::new(cmd);
+    ///     let (rx, _child) = elevated_cmd.spawn_timed().unwrap();
+    ///
+    ///     let mut started = None;
+    ///     for event in rx {
+    ///         match event.kind {
+    ///             CommandEvent::Started { .. } => started = Some(event.at),
+    ///             CommandEvent::Stdout(data) => {
+    ///                 let elapsed = started.map(|s| event.at - s).unwrap_or_default();
+    ///                 println!("{:?} #{}: {}", elapsed, event.seq, String::from_utf8_lossy(&data));
+    ///             }
+    ///             _ => {}
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn spawn_timed(self) -> Result<(Receiver<Event>, CommandChild)> {
+        let (tx, rx) = timed_channel(self.event_capacity, self.replay_buffer);
+        let child = self.spawn_events(tx)?;
+        Ok((rx, child))
+    }
+}
@@ -0,0 +1,150 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Luis Liu. All rights reserved.
+ *  Licensed under the MIT License. See License in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use crate::Command;
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::process::{Command as StdCommand, Output};
+
+// A condition telling that the work of a command is done already
+enum Guard {
+    Creates(PathBuf),
+    Unless(StdCommand),
+    OnlyIf(StdCommand),
+}
+
+/// A command skipped, without prompting the user, when its guards find the work
+/// done already
+///
+/// The guards are only checked by `Guarded::run`, so they cannot be set on a command
+/// executed any other way
+///
+/// # Examples
+///
+/// ```no_run
+/// use elevated_command::{Command, Guarded, Outcome};
+/// use std::process::Command as StdCommand;
+///
+/// fn main() {
+///     let mut cmd = StdCommand::new("mkdir");
+///     cmd.arg("/opt/app");
+///     let mut guarded = Guarded::new(Command::new(cmd));
+///     guarded.creates("/opt/app");
+///     match guarded.run().unwrap() {
+///         Outcome::Completed(output) => println!("{}", output.status),
+///         Outcome::Skipped => println!("nothing to do"),
+///     }
+/// }
+/// ```
+pub struct Guarded {
+    cmd: Command,
+    guards: Vec<Guard>,
+}
+
+/// The result of `Guarded::run`
+#[derive(Debug)]
+pub enum Outcome {
+    /// The command was executed with escalated privileges
+    Completed(Output),
+    /// A guard found the work done already, the command was not executed
+    Skipped,
+}
+
+impl Guarded {
+    /// Constructs a guarded `cmd`, without any guard yet
+    pub fn new(cmd: Command) -> Self {
+        Self { cmd, guards: vec![] }
+    }
+
+    /// Skip the command if `path` exists
+    pub fn creates<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.guards.push(Guard::Creates(path.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Skip the command if `check` exits successfully
+    ///
+    /// `check` is executed without escalated privileges and its output is discarded
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::{Command, Guarded};
+    /// use std::process::Command as StdCommand;
+    ///
+    /// fn main() {
+    ///     let mut cmd = StdCommand::new("useradd");
+    ///     cmd.arg("service");
+    ///     let mut check = StdCommand::new("id");
+    ///     check.arg("service");
+    ///     let mut guarded = Guarded::new(Command::new(cmd));
+    ///     guarded.unless(check);
+    ///     let outcome = guarded.run().unwrap();
+    /// }
+    /// ```
+    pub fn unless(&mut self, check: StdCommand) -> &mut Self {
+        self.guards.push(Guard::Unless(check));
+        self
+    }
+
+    /// Skip the command unless `check` exits successfully
+    ///
+    /// `check` is executed without escalated privileges and its output is discarded
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::{Command, Guarded};
+    /// use std::process::Command as StdCommand;
+    ///
+    /// fn main() {
+    ///     let mut cmd = StdCommand::new("systemctl");
+    ///     cmd.args(["restart", "service"]);
+    ///     let mut check = StdCommand::new("systemctl");
+    ///     check.args(["is-active", "--quiet", "service"]);
+    ///     let mut guarded = Guarded::new(Command::new(cmd));
+    ///     guarded.only_if(check);
+    ///     let outcome = guarded.run().unwrap();
+    /// }
+    /// ```
+    pub fn only_if(&mut self, check: StdCommand) -> &mut Self {
+        self.guards.push(Guard::OnlyIf(check));
+        self
+    }
+
+    /// Execute the command with escalated privileges like `Command::output`, unless
+    /// one of its guards finds the work done already, in which case the user is not
+    /// prompted at all
+    pub fn run(&self) -> Result<Outcome> {
+        for guard in self.guards.iter() {
+            let done = match guard {
+                Guard::Creates(path) => path.exists(),
+                Guard::Unless(check) => copy(check).output()?.status.success(),
+                Guard::OnlyIf(check) => !copy(check).output()?.status.success(),
+            };
+            if done {
+                log::debug!("Skipping {:?}, guarded", self.cmd.cmd.get_program());
+                return Ok(Outcome::Skipped);
+            }
+        }
+        Ok(Outcome::Completed(self.cmd.output()?))
+    }
+}
+
+// `run` takes `&self` like `output`, while executing a std command needs it mutable
+fn copy(cmd: &StdCommand) -> StdCommand {
+    let mut copy = StdCommand::new(cmd.get_program());
+    copy.args(cmd.get_args());
+    for (k, v) in cmd.get_envs() {
+        match v {
+            Some(value) => copy.env(k, value),
+            None => copy.env_remove(k),
+        };
+    }
+    if let Some(dir) = cmd.get_current_dir() {
+        copy.current_dir(dir);
+    }
+    copy
+}
@@ -0,0 +1,103 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Luis Liu. All rights reserved.
+ *  Licensed under the MIT License. See License in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use crate::backpressure::EventSender;
+use crate::Command;
+use crate::CommandChild;
+use crate::CommandEvent;
+use anyhow::Result;
+
+/// Receives the events of a command spawned with `Command::spawn_handler`
+///
+/// The methods are called from the threads reading the output of the process, one
+/// at a time and in the order the events occur. Every method does nothing by default
+pub trait EventHandler: Send + 'static {
+    /// The process wrote `data` to its standard output
+    fn on_stdout(&mut self, _data: &[u8]) {}
+
+    /// The process wrote `data` to its standard error
+    fn on_stderr(&mut self, _data: &[u8]) {}
+
+    /// The process exited with `code`, or was killed by `signal`
+    fn on_exit(&mut self, _code: Option<i32>, _signal: Option<i32>) {}
+
+    /// Waiting for the process failed
+    fn on_error(&mut self, _error: &str) {}
+
+    /// Any other event, like `CommandEvent::AuthRequested` or `CommandEvent::Started`
+    fn on_event(&mut self, _event: CommandEvent) {}
+}
+
+impl Command {
+    /// Spawn the command like `Command::spawn`, calling `handler` with every event
+    /// instead of sending it through a channel
+    ///
+    /// `handler` is called from the thread reading the output, so no thread has to
+    /// wait on a receiver. The output is not read while it runs, which holds back the
+    /// process like `Backpressure::Block`, and `Command::event_capacity` is ignored
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::{Command, CommandEvent};
+    /// use std::process::Command as StdCommand;
+    ///
+    /// fn main() {
+    ///     let cmd = StdCommand::new("path to the application");
+    ///     let elevated_cmd = Command::new(cmd);
+    ///     let _child = elevated_cmd.spawn_with(|event| match event {
+    ///         CommandEvent::Stdout(data) => print!("{}", String::from_utf8_lossy(&data)),
+    ///         CommandEvent::Terminated { code, .. } => println!("exited with {:?}", code),
+    ///         _ => {}
+    ///     }).unwrap();
+    /// }
+    /// ```
+    pub fn spawn_with<F>(self, handler: F) -> Result<CommandChild>
+    where
+        F: FnMut(CommandEvent) + Send + 'static,
+    {
+        let tx = EventSender::handler(handler, self.replay_buffer);
+        self.spawn_events(tx)
+    }
+
+    /// Spawn the command like `Command::spawn_with`, dispatching the events to the
+    /// methods of `handler`
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::{Command, EventHandler};
+    /// use std::process::Command as StdCommand;
+    ///
+    /// struct Progress {
+    ///     lines: usize,
+    /// }
+    ///
+    /// impl EventHandler for Progress {
+    ///     fn on_stdout(&mut self, data: &[u8]) {
+    ///         self.lines += data.iter().filter(|&&b| b == b'\n').count();
+    ///     }
+    ///
+    ///     fn on_exit(&mut self, code: Option<i32>, _signal: Option<i32>) {
+    ///         println!("{} lines, exited with {:?}", self.lines, code);
+    ///     }
+    /// }
+    ///
+    /// fn main() {
+    ///     let cmd = StdCommand::new("path to the application");
+    ///     let elevated_cmd = Command::new(cmd);
+    ///     let _child = elevated_cmd.spawn_handler(Progress { lines: 0 }).unwrap();
+    /// }
+    /// ```
+    pub fn spawn_handler<H: EventHandler>(self, mut handler: H) -> Result<CommandChild> {
+        self.spawn_with(move |event| match event {
+            CommandEvent::Stdout(data) => handler.on_stdout(&data),
+            CommandEvent::Stderr(data) => handler.on_stderr(&data),
+            CommandEvent::Terminated { code, signal, .. } => handler.on_exit(code, signal),
+            CommandEvent::Error(error) => handler.on_error(&error),
+            event => handler.on_event(event),
+        })
+    }
+}
@@ -0,0 +1,110 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Luis Liu. All rights reserved.
+ *  Licensed under the MIT License. See License in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+//! The privileged helper commands are executed through when `Command::helper` is set
+//!
+//! The application ships a tiny executable calling [`run`], installs it as root and
+//! registers it in a polkit `.policy` file with the `org.freedesktop.policykit.exec.path`
+//! annotation, so `pkexec` authorizes it under the application's own action id
+//! instead of `org.freedesktop.policykit.exec`
+//!
+//! The helper executes its arguments as root for anyone authorized for that action,
+//! so it must only accept the commands the application actually runs, see [`run`]
+use anyhow::{anyhow, bail, Result};
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::process::Command as StdCommand;
+
+/// Build the arguments the helper is invoked with
+pub(crate) fn helper_args(envs: &[(OsString, OsString)], program: &OsString, args: &[OsString]) -> Vec<OsString> {
+    let mut helper_args = vec![];
+    for (k, v) in envs {
+        let mut env = k.clone();
+        env.push("=");
+        env.push(v);
+        helper_args.push(OsString::from("--env"));
+        helper_args.push(env);
+    }
+    helper_args.push(OsString::from("--"));
+    helper_args.push(program.clone());
+    helper_args.extend(args.iter().cloned());
+    helper_args
+}
+
+/// The command the helper was invoked to execute
+#[derive(Debug, Clone)]
+pub struct Request {
+    /// The environment variables passed with `--env`
+    pub envs: Vec<(OsString, OsString)>,
+    pub program: OsString,
+    pub args: Vec<OsString>,
+}
+
+impl Request {
+    fn parse(mut args: impl Iterator<Item = OsString>) -> Result<Self> {
+        let mut envs = vec![];
+        loop {
+            let arg = args.next().ok_or(anyhow!("missing program"))?;
+            match arg.as_bytes() {
+                b"--" => break,
+                b"--env" => {
+                    let env = args.next().ok_or(anyhow!("missing value for --env"))?;
+                    let bytes = env.as_bytes();
+                    let pos = bytes.iter().position(|b| *b == b'=').ok_or(anyhow!("invalid --env value"))?;
+                    envs.push((
+                        OsString::from(OsStr::from_bytes(&bytes[..pos])),
+                        OsString::from(OsStr::from_bytes(&bytes[pos + 1..])),
+                    ));
+                }
+                _ => bail!("unexpected argument: {:?}", arg),
+            }
+        }
+        let program = args.next().ok_or(anyhow!("missing program"))?;
+        Ok(Self {
+            envs,
+            program,
+            args: args.collect(),
+        })
+    }
+}
+
+/// Execute the command described by the arguments of the current process if `allow`
+/// accepts it, only returning if it was refused or could not be executed
+///
+/// `pkexec` authorizes the helper, not the command it is invoked with: any user allowed
+/// to use the action of the helper can run it directly with whatever arguments. `allow`
+/// is the only thing keeping the helper from executing any program as root, it should
+/// accept nothing but the exact programs, arguments and environment variables the
+/// application needs
+///
+/// # Examples
+///
+/// ```no_run
+/// use elevated_command::helper;
+///
+/// fn main() {
+///     let err = helper::run(|request| {
+///         request.program == "/usr/bin/systemctl"
+///             && request.args == ["restart", "example.service"]
+///             && request.envs.iter().all(|(k, _)| k == "DISPLAY" || k == "XAUTHORITY" || k == "HOME")
+///     })
+///     .unwrap_err();
+///     eprintln!("{}", err);
+///     std::process::exit(127);
+/// }
+/// ```
+pub fn run(allow: impl Fn(&Request) -> bool) -> Result<()> {
+    let request = Request::parse(env::args_os().skip(1))?;
+    if !allow(&request) {
+        bail!("the helper does not allow executing {:?}", request.program);
+    }
+    let err = StdCommand::new(&request.program)
+        .args(&request.args)
+        .envs(request.envs)
+        .exec();
+    Err(err.into())
+}
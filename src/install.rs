@@ -0,0 +1,124 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Luis Liu. All rights reserved.
+ *  Licensed under the MIT License. See License in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use crate::Command;
+use anyhow::{anyhow, bail, Result};
+use std::env;
+use std::fs::{remove_dir_all, DirBuilder, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Command as StdCommand;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// The file is first copied next to its destination with a `.tmp` suffix, which neither
+// polkit (only loads `*.rules`) nor sudo (skips names containing a dot) would pick up,
+// then checked and renamed into place, so a half-written file is never loaded
+const INSTALL_SCRIPT: &str = r#"set -e
+tmp="$3.tmp"
+trap 'rm -f "$tmp"' EXIT
+install -m "$1" -o 0 -g 0 "$2" "$tmp"
+if [ -n "$4" ]; then "$4" -cf "$tmp" >&2; fi
+mv -f "$tmp" "$3"
+"#;
+
+/// Install `contents` as the root owned file `dest` with the given octal `mode`,
+/// going through the regular elevation path of `Command`
+///
+/// If `check` is set, it names a program accepting `-cf <file>` (like `visudo`)
+/// that must accept the staged file before it replaces `dest`
+pub(crate) fn install_file(contents: &[u8], dest: &Path, mode: &str, check: Option<&str>) -> Result<()> {
+    let name = dest.file_name().and_then(|s| s.to_str()).ok_or(anyhow!("invalid destination"))?;
+    let staging = Staging::new()?;
+    let staged = staging.file(name, contents)?;
+
+    let mut cmd = StdCommand::new("/bin/sh");
+    cmd.arg("-c")
+        .arg(INSTALL_SCRIPT)
+        .arg("sh")
+        .arg(mode)
+        .arg(&staged)
+        .arg(dest)
+        .arg(check.unwrap_or(""));
+    let output = Command::new(cmd).output();
+    drop(staging);
+
+    let output = output?;
+    if !output.status.success() {
+        bail!(
+            "failed to install {}: {}",
+            dest.display(),
+            String::from_utf8_lossy(&output.stderr).trim_end(),
+        );
+    }
+    Ok(())
+}
+
+// A private directory in the temporary directory holding the files handed to root.
+// Other users can neither create nor replace files in it, unlike in the temporary
+// directory itself, where a predictable name could be taken over before root reads it
+pub(crate) struct Staging {
+    dir: PathBuf,
+}
+
+impl Staging {
+    pub(crate) fn new() -> Result<Self> {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.subsec_nanos();
+        for attempt in 0..16u32 {
+            let dir = env::temp_dir().join(format!(
+                "elevated_cmd_staging_{}_{:x}_{}",
+                std::process::id(),
+                nanos,
+                attempt,
+            ));
+            // fails if anything exists under that name, a symlink included
+            match DirBuilder::new().mode(0o700).create(&dir) {
+                Ok(()) => return Ok(Self { dir }),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        bail!("failed to create a staging directory in {}", env::temp_dir().display())
+    }
+
+    pub(crate) fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Write `contents` to a new file only the current user can read
+    pub(crate) fn file(&self, name: &str, contents: &[u8]) -> Result<PathBuf> {
+        let path = self.dir.join(name);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?;
+        file.write_all(contents)?;
+        Ok(path)
+    }
+}
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        let _ = remove_dir_all(&self.dir);
+    }
+}
+
+/// Remove the root owned file `dest` through the regular elevation path of `Command`
+///
+/// Removing a file which does not exist is not an error
+pub(crate) fn uninstall_file(dest: &Path) -> Result<()> {
+    let mut cmd = StdCommand::new("/bin/rm");
+    cmd.arg("-f").arg(dest);
+    let output = Command::new(cmd).output()?;
+    if !output.status.success() {
+        bail!(
+            "failed to remove {}: {}",
+            dest.display(),
+            String::from_utf8_lossy(&output.stderr).trim_end(),
+        );
+    }
+    Ok(())
+}
@@ -7,9 +7,13 @@
 //! 
 //! elevated-command - Run command using `sudo`, prompting the user with a graphical OS dialog if necessary
 use std::convert::From;
+use std::fmt;
 use std::process::Command as StdCommand;
 use std::path::PathBuf;
 use anyhow::Result;
+use retry::AuthFailureHandler;
+#[cfg(target_os = "linux")]
+use pipe::Stdin;
 
 /// Wrap of std::process::command and escalate privileges while executing
 pub struct Command {
@@ -18,33 +22,250 @@ pub struct Command {
     icon: Option<Vec<u8>>,
     #[allow(dead_code)]
     name: Option<String>,
+    non_interactive: bool,
+    auth_retries: usize,
+    on_auth_failure: Option<AuthFailureHandler>,
+    event_capacity: Option<(usize, Backpressure)>,
+    replay_buffer: usize,
+    #[cfg(target_os = "linux")]
+    action_id: Option<String>,
+    #[cfg(target_os = "linux")]
+    details: Vec<(String, String)>,
+    #[cfg(target_os = "linux")]
+    helper: Option<PathBuf>,
+    #[cfg(target_os = "linux")]
+    pty: bool,
+    #[cfg(target_os = "linux")]
+    stdin: Stdin,
+    #[cfg(target_os = "linux")]
+    stdout: StreamMode,
+    #[cfg(target_os = "linux")]
+    stderr: StreamMode,
+    #[cfg(all(target_os = "linux", feature = "polkit-dbus"))]
+    cancellation: Option<Cancellation>,
 }
 
 // Event types for spawn
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum CommandEvent {
+    /// The user is expected to be asked to authenticate
+    AuthRequested,
+    /// The user authenticated, the command runs with escalated privileges
+    AuthGranted,
+    /// The user failed to authenticate, the command does not run
+    AuthDenied,
+    /// The program was started, after `CommandEvent::AuthGranted` when the user was
+    /// asked to authenticate. `pid` is the elevating process like `pkexec`. This event
+    /// is only sent on `Linux`
+    Started { pid: u32 },
     Stdout(Vec<u8>),
     Stderr(Vec<u8>),
-    Terminated { code: Option<i32> },
+    /// The process exited with `code`, or on Unix was killed by `signal`. `dropped` counts
+    /// the output bytes discarded by `Backpressure::DropOldest`
+    Terminated { code: Option<i32>, signal: Option<i32>, dropped: u64 },
     Error(String),
 }
 
+/// Where the output of a stream of the elevated process goes, see `Command::stdout`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum StreamMode {
+    /// Collected into `Output`, or sent as `CommandEvent`s by `Command::spawn`
+    #[default]
+    Capture,
+    /// Written straight to the same stream of the current program
+    Inherit,
+    /// Discarded
+    Null,
+    /// Written to the given file, which is created or truncated
+    File(PathBuf),
+    /// Captured and also written to the same stream of the current program
+    Tee,
+}
+
+/// Whether executing a command with escalated privileges would need the user's consent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthStatus {
+    /// The command would run without showing a dialog
+    Authorized,
+    /// The user would be prompted for a password or a confirmation
+    ChallengeRequired,
+    /// The command would not be allowed to run at all
+    Denied,
+}
+
+/// Errors reported while obtaining escalated privileges
+/// 
+/// They are returned wrapped in `anyhow::Error`, use `downcast_ref` to tell them apart
+/// 
+/// # Examples
+///
+/// ```no_run
+/// use elevated_command::{Command, ElevationError};
+/// use std::process::Command as StdCommand;
+///
+/// fn main() {
+///     let cmd = StdCommand::new("path to the application");
+///     let elevated_cmd = Command::new(cmd);
+///     if let Err(e) = elevated_cmd.output() {
+///         if let Some(ElevationError::AuthDismissed) = e.downcast_ref::<ElevationError>() {
+///             println!("the dialog was dismissed");
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ElevationError {
+    /// The user dismissed the authentication dialog
+    AuthDismissed,
+    /// The user is not allowed to execute the command with escalated privileges,
+    /// or failed to authenticate
+    AuthDenied,
+    /// The authorization was cancelled before the user answered, or while
+    /// waiting for its turn in the `ElevationQueue`
+    Cancelled,
+    /// The application sandbox does not allow elevating on the host,
+    /// the message names the missing permission
+    HostAccessDenied(String),
+    /// None of the supported ways to escalate privileges is available
+    NoElevationAvailable,
+    /// The command would prompt the user while `Command::non_interactive` is set
+    InteractionRequired,
+}
+
+impl fmt::Display for ElevationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ElevationError::AuthDismissed => write!(f, "authentication dialog dismissed"),
+            ElevationError::AuthDenied => write!(f, "not authorized"),
+            ElevationError::Cancelled => write!(f, "authorization cancelled"),
+            ElevationError::HostAccessDenied(reason) => write!(f, "no access to the host: {}", reason),
+            ElevationError::NoElevationAvailable => write!(f, "no way to escalate privileges available"),
+            ElevationError::InteractionRequired => write!(f, "user interaction required"),
+        }
+    }
+}
+
+impl std::error::Error for ElevationError {}
+
+/// The way a command is executed with escalated privileges
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// The current program is elevated already, the command is executed as is
+    Direct,
+    /// `pkexec`, showing the polkit authentication agent
+    Pkexec,
+    /// `sudo`, asking for the password on the terminal
+    Sudo,
+    /// `doas`, asking for the password on the terminal
+    Doas,
+    /// The AppleScript applet on MacOS, showing the system password dialog
+    Applet,
+    /// User Account Control on Windows
+    Uac,
+}
+
+/// A backend installed on this system, see `Command::can_elevate`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackendAvailability {
+    pub backend: Backend,
+    /// The version of the tool behind the backend, if it reports one
+    pub version: Option<String>,
+    /// Whether a graphical dialog can be shown to authenticate
+    pub graphical_agent: bool,
+    /// Whether the user can authenticate on the terminal
+    pub text_agent: bool,
+    /// Whether commands would currently be executed without prompting at all
+    pub non_interactive: bool,
+}
+
 // Child process handle for spawn
 pub struct CommandChild {
     // On macOS with elevated_command, we don't have a real child process handle
     // because the applet runs and exits immediately, but the elevated process continues
     // We track the output files location instead
     _output_dir: PathBuf,
+    // On Linux the elevating process is our own child
+    #[cfg(target_family = "unix")]
+    process: Option<std::sync::Arc<pipe::Process>>,
+    // The input of the elevating process, if it can be written to
+    #[cfg(target_family = "unix")]
+    stdin: Option<CommandStdin>,
+    // The master side of the pseudo-terminal, see `Command::pty`
+    #[cfg(target_os = "linux")]
+    pty: Option<std::fs::File>,
+    // The subscribers of the events, see `CommandChild::subscribe`
+    broadcast: std::sync::Arc<broadcast::Broadcast>,
 }
 
 impl CommandChild {
-    // Note: killing the elevated process is not possible with this approach
+    // Note: killing the elevated process is not possible with the applet
     // The applet has already exited, and we don't have the PID of the elevated process
+    // When the elevating process is our own child, it is sent SIGTERM unless it was
+    // reaped already, which fails if it runs as root
     pub fn kill(&self) -> Result<()> {
+        #[cfg(target_family = "unix")]
+        if let Some(ref process) = self.process {
+            process.kill()?;
+        }
         // Cannot kill - the applet wrapper already exited
         // The elevated process is running independently
         Ok(())
     }
+
+    /// Write `data` to the input of the elevated process
+    ///
+    /// Only available when the command runs in a pseudo-terminal, see `Command::pty`,
+    /// or with `Command::stdin_piped`, as long as `CommandChild::stdin` was not taken
+    pub fn write_stdin(&self, data: &[u8]) -> Result<()> {
+        #[cfg(target_family = "unix")]
+        if let Some(ref stdin) = self.stdin {
+            use std::io::Write;
+            let mut file = &stdin.file;
+            file.write_all(data)?;
+            return Ok(());
+        }
+        let _ = data;
+        anyhow::bail!("the input of the elevated process is not available")
+    }
+
+    /// Take the input of the elevated process, to stream data to it
+    ///
+    /// Only available on `Linux` with `Command::stdin_piped` or `Command::pty`
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::Command;
+    /// use std::io::Write;
+    /// use std::process::Command as StdCommand;
+    ///
+    /// fn main() {
+    ///     let cmd = StdCommand::new("psql");
+    ///     let mut elevated_cmd = Command::new(cmd);
+    ///     elevated_cmd.stdin_piped(true);
+    ///
+    ///     let (rx, mut child) = elevated_cmd.spawn().unwrap();
+    ///     let mut stdin = child.stdin().unwrap();
+    ///     stdin.write_all(b"SELECT 1;\n").unwrap();
+    ///     stdin.close().unwrap();
+    ///     while rx.recv().is_ok() {}
+    /// }
+    /// ```
+    #[cfg(target_family = "unix")]
+    pub fn stdin(&mut self) -> Option<CommandStdin> {
+        self.stdin.take()
+    }
+
+    /// Resize the pseudo-terminal the elevated process runs in, see `Command::pty`
+    pub fn resize(&self, cols: u16, rows: u16) -> Result<()> {
+        #[cfg(target_os = "linux")]
+        if let Some(ref pty) = self.pty {
+            return pty::resize(pty, cols, rows);
+        }
+        let _ = (cols, rows);
+        anyhow::bail!("the elevated process does not run in a pseudo-terminal")
+    }
 }
 
 /// Command initialization shares the same logic across all the platforms
@@ -62,7 +283,10 @@ impl Command {
     /// without the ones inherited from the parent process
     /// 
     /// And the environment variables would only be taken on Linux and MacOS, 
-    /// they would be ignored on Windows
+    /// they would be ignored on Windows. With sudo or doas, used on Linux and by
+    /// `Command::non_interactive` on MacOS, only the variables their configuration
+    /// allows are passed: sudo refuses to run the command with any other, see
+    /// `SudoersRule::env_keep`, while doas drops them unless kept with `setenv`
     /// 
     /// Current working directory would be the following while executing the command:
     ///   - %SystemRoot%\System32 on Windows
@@ -93,6 +317,27 @@ impl Command {
             cmd,
             icon: None,
             name: None,
+            non_interactive: false,
+            auth_retries: 0,
+            on_auth_failure: None,
+            event_capacity: None,
+            replay_buffer: 0,
+            #[cfg(target_os = "linux")]
+            action_id: None,
+            #[cfg(target_os = "linux")]
+            details: vec![],
+            #[cfg(target_os = "linux")]
+            helper: None,
+            #[cfg(target_os = "linux")]
+            pty: false,
+            #[cfg(target_os = "linux")]
+            stdin: Stdin::Null,
+            #[cfg(target_os = "linux")]
+            stdout: StreamMode::Capture,
+            #[cfg(target_os = "linux")]
+            stderr: StreamMode::Capture,
+            #[cfg(all(target_os = "linux", feature = "polkit-dbus"))]
+            cancellation: None,
         }
     }
 
@@ -142,7 +387,7 @@ impl Command {
     ///
     /// fn main() {
     ///     let mut cmd = StdCommand::new("path to the application");
-    ///     let elevated_cmd = Command::new(cmd);
+    ///     let mut elevated_cmd = Command::new(cmd);
     ///     let cmd = elevated_cmd.get_mut();
     /// }
     /// ```
@@ -162,8 +407,8 @@ impl Command {
     ///
     /// fn main() {
     ///     let mut cmd = StdCommand::new("path to the application");
-    ///     let elevated_cmd = Command::new(cmd);
-    ///     elevated_cmd.icon(include_bytes!("path to the icon").to_vec());
+    ///     let mut elevated_cmd = Command::new(cmd);
+    ///     elevated_cmd.icon(std::fs::read("path to the icon").unwrap());
     /// }
     /// ```
     pub fn icon(&mut self, icon: Vec<u8>) -> &mut Self {
@@ -183,7 +428,7 @@ impl Command {
     ///
     /// fn main() {
     ///     let mut cmd = StdCommand::new("path to the application");
-    ///     let elevated_cmd = Command::new(cmd);
+    ///     let mut elevated_cmd = Command::new(cmd);
     ///     elevated_cmd.name("some name".to_string());
     /// }
     /// ```
@@ -191,6 +436,36 @@ impl Command {
         self.name = Some(name);
         self
     }
+
+    /// Never prompt the user, failing with `ElevationError::InteractionRequired`
+    /// unless the command can be executed without asking for credentials
+    /// 
+    /// The command is executed with `sudo -n` or `doas -n`, `pkexec` and the `MacOS`
+    /// applet are only used when polkit or cached `sudo` credentials authorize it upfront,
+    /// on Windows the current program has to be elevated already
+    /// 
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::{Command, ElevationError};
+    /// use std::process::Command as StdCommand;
+    ///
+    /// fn main() {
+    ///     let cmd = StdCommand::new("path to the application");
+    ///     let mut elevated_cmd = Command::new(cmd);
+    ///     elevated_cmd.non_interactive(true);
+    ///     match elevated_cmd.output() {
+    ///         Err(e) if e.downcast_ref() == Some(&ElevationError::InteractionRequired) => {
+    ///             println!("skipped, needs a password");
+    ///         }
+    ///         result => println!("{:?}", result),
+    ///     }
+    /// }
+    /// ```
+    pub fn non_interactive(&mut self, non_interactive: bool) -> &mut Self {
+        self.non_interactive = non_interactive;
+        self
+    }
 }
 
 impl From<StdCommand> for Command {
@@ -198,11 +473,7 @@ impl From<StdCommand> for Command {
     /// 
     /// It is similiar with the construct method
     fn from(cmd: StdCommand) -> Self {
-        Self {
-            cmd,
-            icon: None,
-            name: None,
-        }
+        Self::new(cmd)
     }
 }
 
@@ -212,3 +483,57 @@ mod windows;
 mod linux;
 #[cfg(target_os = "macos")]
 mod macos;
+#[cfg(target_family = "unix")]
+mod install;
+#[cfg(target_family = "unix")]
+mod pipe;
+#[cfg(target_os = "linux")]
+mod pty;
+#[cfg(target_os = "linux")]
+mod handler;
+#[cfg(target_family = "unix")]
+mod batch;
+#[cfg(target_family = "unix")]
+mod coalesce;
+#[cfg(target_family = "unix")]
+mod transaction;
+mod queue;
+mod guard;
+mod retry;
+mod lines;
+mod event;
+mod backpressure;
+mod broadcast;
+mod details;
+#[cfg(target_os = "linux")]
+pub mod polkit;
+#[cfg(target_family = "unix")]
+pub mod sudoers;
+#[cfg(target_os = "linux")]
+pub mod helper;
+#[cfg(all(target_os = "linux", feature = "polkit-dbus"))]
+mod dbus;
+
+pub use queue::{ElevationQueue, QueueOrder};
+pub use guard::{Guarded, Outcome};
+pub use retry::RetryDecision;
+pub use lines::{LineEvent, Lines};
+pub use event::Event;
+pub use backpressure::Backpressure;
+pub use details::ElevatedOutput;
+#[cfg(target_os = "linux")]
+pub use details::ResourceUsage;
+#[cfg(target_family = "unix")]
+pub use batch::{Batch, BatchEvent};
+#[cfg(target_family = "unix")]
+pub use coalesce::Coalescer;
+#[cfg(target_family = "unix")]
+pub use transaction::{Transaction, TransactionReport};
+#[cfg(target_family = "unix")]
+pub use pipe::CommandStdin;
+#[cfg(target_os = "linux")]
+pub use linux::{Container, Environment, Sandbox};
+#[cfg(target_os = "linux")]
+pub use handler::EventHandler;
+#[cfg(all(target_os = "linux", feature = "polkit-dbus"))]
+pub use dbus::Cancellation;
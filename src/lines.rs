@@ -0,0 +1,246 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Luis Liu. All rights reserved.
+ *  Licensed under the MIT License. See License in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use crate::CommandEvent;
+use std::collections::VecDeque;
+use std::sync::mpsc::Receiver;
+
+// Lines longer than this are split unless `Lines::max_line_length` says otherwise
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// An event yielded by `Lines`
+#[derive(Debug, Clone)]
+pub enum LineEvent {
+    /// A line written to the standard output, without its line ending
+    StdoutLine(String),
+    /// A line written to the standard error, without its line ending
+    StderrLine(String),
+    /// Any other event, passed through as is
+    Event(CommandEvent),
+}
+
+/// Turn the output chunks of `Command::spawn` into whole lines
+///
+/// Chunks are cut at arbitrary byte boundaries, so a line or a multi-byte character
+/// may be split across them. The partial line is kept until its end arrives, and the
+/// last line without a line ending is yielded before `CommandEvent::Terminated`.
+/// Invalid UTF-8 is replaced with `U+FFFD`
+///
+/// # Examples
+///
+/// ```no_run
+/// use elevated_command::{Command, LineEvent, Lines};
+/// use std::process::Command as StdCommand;
+///
+/// fn main() {
+///     let cmd = StdCommand::new("path to the application");
+///     let elevated_cmd = Command::new(cmd);
+///     let (rx, _child) = elevated_cmd.spawn().unwrap();
+///
+///     let mut lines = Lines::new(rx);
+///     lines.max_line_length(1024);
+///     for event in lines {
+///         match event {
+///             LineEvent::StdoutLine(line) => println!("OUT: {}", line),
+///             LineEvent::StderrLine(line) => println!("ERR: {}", line),
+///             LineEvent::Event(event) => println!("{:?}", event),
+///         }
+///     }
+/// }
+/// ```
+pub struct Lines {
+    rx: Receiver<CommandEvent>,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    max_line_length: usize,
+    pending: VecDeque<LineEvent>,
+    done: bool,
+}
+
+impl Lines {
+    /// Constructs an adapter reading the events from `rx`
+    pub fn new(rx: Receiver<CommandEvent>) -> Self {
+        Self {
+            rx,
+            stdout: vec![],
+            stderr: vec![],
+            max_line_length: MAX_LINE_LENGTH,
+            pending: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// Split lines longer than `max` bytes, 64 KiB by default
+    pub fn max_line_length(&mut self, max: usize) -> &mut Self {
+        self.max_line_length = max.max(1);
+        self
+    }
+
+    fn flush(&mut self) {
+        if !self.stdout.is_empty() {
+            let line = decode(&std::mem::take(&mut self.stdout));
+            self.pending.push_back(LineEvent::StdoutLine(line));
+        }
+        if !self.stderr.is_empty() {
+            let line = decode(&std::mem::take(&mut self.stderr));
+            self.pending.push_back(LineEvent::StderrLine(line));
+        }
+    }
+}
+
+impl Iterator for Lines {
+    type Item = LineEvent;
+
+    fn next(&mut self) -> Option<LineEvent> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(event);
+            }
+            if self.done {
+                return None;
+            }
+            match self.rx.recv() {
+                Ok(CommandEvent::Stdout(data)) => {
+                    for line in split(&mut self.stdout, &data, self.max_line_length) {
+                        self.pending.push_back(LineEvent::StdoutLine(line));
+                    }
+                }
+                Ok(CommandEvent::Stderr(data)) => {
+                    for line in split(&mut self.stderr, &data, self.max_line_length) {
+                        self.pending.push_back(LineEvent::StderrLine(line));
+                    }
+                }
+                Ok(event) => {
+                    // the process is gone, nothing completes the partial lines anymore
+                    if matches!(event, CommandEvent::Terminated { .. } | CommandEvent::Error(_)) {
+                        self.flush();
+                    }
+                    self.pending.push_back(LineEvent::Event(event));
+                }
+                Err(_) => {
+                    self.flush();
+                    self.done = true;
+                }
+            }
+        }
+    }
+}
+
+// Append `data` to the partial line in `buffer`, returning the lines completed.
+// A line ending never occurs inside a multi-byte character, so complete lines
+// always decode without splitting one
+fn split(buffer: &mut Vec<u8>, data: &[u8], max: usize) -> Vec<String> {
+    buffer.extend_from_slice(data);
+    let mut lines = vec![];
+    let mut start = 0;
+    loop {
+        let rest = &buffer[start..];
+        match rest.iter().position(|&b| b == b'\n') {
+            Some(end) if end <= max => {
+                lines.push(decode(&rest[..end]));
+                start += end + 1;
+            }
+            _ if rest.len() > max => {
+                // cut before a continuation byte would split a character
+                let mut end = max;
+                while end > 0 && rest[end] & 0xC0 == 0x80 {
+                    end -= 1;
+                }
+                if end == 0 {
+                    end = max;
+                }
+                lines.push(decode(&rest[..end]));
+                start += end;
+            }
+            _ => break,
+        }
+    }
+    buffer.drain(..start);
+    lines
+}
+
+fn decode(line: &[u8]) -> String {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    String::from_utf8_lossy(line).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    fn lines(events: Vec<CommandEvent>, max: usize) -> Vec<String> {
+        let (tx, rx) = channel();
+        for event in events {
+            tx.send(event).unwrap();
+        }
+        drop(tx);
+        let mut lines = Lines::new(rx);
+        lines.max_line_length(max);
+        lines
+            .map(|event| match event {
+                LineEvent::StdoutLine(line) => format!("out {}", line),
+                LineEvent::StderrLine(line) => format!("err {}", line),
+                LineEvent::Event(event) => format!("{:?}", event),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn split_joins_a_character_split_across_chunks() {
+        let text = "héllo\n".as_bytes();
+        let mut buffer = vec![];
+        // cut in the middle of `é`
+        assert!(split(&mut buffer, &text[..2], 64).is_empty());
+        assert_eq!(split(&mut buffer, &text[2..], 64), ["héllo"]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn split_strips_a_line_ending_split_across_chunks() {
+        let mut buffer = vec![];
+        assert!(split(&mut buffer, b"first\r", 64).is_empty());
+        assert_eq!(split(&mut buffer, b"\nsecond\r\nthi", 64), ["first", "second"]);
+        assert_eq!(buffer, b"thi");
+    }
+
+    #[test]
+    fn split_cuts_long_lines_between_characters() {
+        let mut buffer = vec![];
+        // `é` takes the bytes 3 and 4, a cut after 4 bytes would split it
+        assert_eq!(split(&mut buffer, "abcédef\n".as_bytes(), 4), ["abc", "éde", "f"]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn split_keeps_a_long_line_which_is_not_complete() {
+        let mut buffer = vec![];
+        assert_eq!(split(&mut buffer, b"abcdefg", 4), ["abcd"]);
+        assert_eq!(buffer, b"efg");
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced() {
+        let mut buffer = vec![];
+        assert_eq!(split(&mut buffer, b"a\xffb\n", 64), ["a\u{fffd}b"]);
+    }
+
+    #[test]
+    fn partial_lines_are_flushed_before_terminated() {
+        let events = vec![
+            CommandEvent::Stdout(b"partial".to_vec()),
+            CommandEvent::Stderr(b"error\nrest".to_vec()),
+            CommandEvent::Terminated { code: Some(0), signal: None, dropped: 0 },
+        ];
+        let terminated = format!("{:?}", events[2]);
+        assert_eq!(lines(events, 64), ["err error", "out partial", "err rest", &terminated]);
+    }
+
+    #[test]
+    fn partial_lines_are_flushed_when_the_channel_closes() {
+        let events = vec![CommandEvent::Stdout(b"one\ntwo".to_vec())];
+        assert_eq!(lines(events, 64), ["out one", "out two"]);
+    }
+}
@@ -3,13 +3,64 @@
  *  Licensed under the MIT License. See License in the project root for license information.
  *--------------------------------------------------------------------------------------------*/
 
-use crate::Command;
-use anyhow::{anyhow, Result};
+use crate::helper::helper_args;
+use crate::polkit::PKEXEC_ACTION_ID;
+use crate::sudoers::{env_arg, resolve_program};
+use crate::pipe::{spawn_piped, wait_exited, AuthCheck, Launching, StartCheck, Stdin, START_POLL_INTERVAL};
+use crate::pty::spawn_pty;
+use crate::queue::Turn;
+use crate::backpressure::{event_channel, EventSender};
+use crate::{AuthStatus, Backend, BackendAvailability, Command, CommandChild, CommandEvent, ElevationError, StreamMode};
+use crate::{ElevatedOutput, ResourceUsage};
+use crate::pipe;
+#[cfg(feature = "polkit-dbus")]
+use crate::Cancellation;
+use anyhow::{anyhow, bail, Result};
 use std::env;
-use std::ffi::OsStr;
-use std::path::PathBuf;
-use std::process::{Command as StdCommand, Output};
+use std::ffi::{OsStr, OsString};
+use std::fs::{read_dir, read_to_string};
+use std::io;
+use std::mem;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command as StdCommand, ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
 use std::str::FromStr;
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+const PKEXEC: &str = "/bin/pkexec";
+const FLATPAK_INFO: &str = "/.flatpak-info";
+// Forwarded to the host so the authentication agent shows up in the right session
+const HOST_ENV: [&str; 4] = ["DISPLAY", "WAYLAND_DISPLAY", "XAUTHORITY", "XDG_RUNTIME_DIR"];
+
+/// The container runtime the current program is running in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Container {
+    Docker,
+    Podman,
+    Lxc,
+    /// Any other runtime, named after the `container` environment variable if set
+    Other(String),
+}
+
+/// The environment the current program is running in, see `Command::environment`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Environment {
+    pub container: Option<Container>,
+    pub wsl: bool,
+    pub sandbox: Option<Sandbox>,
+}
+
+/// The application sandbox the current program is running in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sandbox {
+    Flatpak,
+    Snap,
+}
 
 /// The implementation of state check and elevated executing varies on each platform
 impl Command {
@@ -31,11 +82,178 @@ impl Command {
         let uid = unsafe { 
             libc::getuid()
         };
-        if uid == 0 { 
-            true 
-        } else { 
-            false 
-        }
+        uid == 0
+    }
+
+    /// The polkit action id the command is authorized under while executing
+    /// 
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::Command;
+    /// use std::process::Command as StdCommand;
+    ///
+    /// fn main() {
+    ///     let cmd = StdCommand::new("path to the application");
+    ///     let elevated_cmd = Command::new(cmd);
+    ///     assert_eq!(elevated_cmd.get_action_id(), "org.freedesktop.policykit.exec");
+    /// }
+    /// ```
+    pub fn get_action_id(&self) -> &str {
+        self.action_id.as_deref().unwrap_or(PKEXEC_ACTION_ID)
+    }
+
+    /// Set the polkit action id the command is authorized under
+    /// 
+    /// `pkexec` only uses a custom action for the program named by its
+    /// `org.freedesktop.policykit.exec.path` annotation, so this should come along
+    /// with `Command::helper`. This method is only applicable on `Linux`
+    /// 
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::Command;
+    /// use std::process::Command as StdCommand;
+    ///
+    /// fn main() {
+    ///     let cmd = StdCommand::new("path to the application");
+    ///     let mut elevated_cmd = Command::new(cmd);
+    ///     elevated_cmd
+    ///         .action_id("com.example.maintenance")
+    ///         .helper("/usr/libexec/example-helper".into());
+    /// }
+    /// ```
+    pub fn action_id(&mut self, action_id: &str) -> &mut Self {
+        self.action_id = Some(action_id.to_string());
+        self
+    }
+
+    /// Add a detail passed along with the authorization check, which polkit rules
+    /// can read with `action.lookup(key)` and which authentication dialogs may show
+    /// 
+    /// This method is only applicable on `Linux`
+    pub fn detail(&mut self, key: &str, value: &str) -> &mut Self {
+        self.details.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Run the command in a pseudo-terminal, for interactive programs which
+    /// expect a terminal rather than pipes
+    /// 
+    /// `Command::spawn` then forwards all output as `CommandEvent::Stdout`,
+    /// input is written with `CommandChild::write_stdin` and the terminal is resized
+    /// with `CommandChild::resize`. This method is only applicable on `Linux`
+    /// 
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::{Command, CommandEvent};
+    /// use std::process::Command as StdCommand;
+    ///
+    /// fn main() {
+    ///     let cmd = StdCommand::new("fdisk");
+    ///     let mut elevated_cmd = Command::new(cmd);
+    ///     elevated_cmd.pty(true);
+    ///
+    ///     let (rx, child) = elevated_cmd.spawn().unwrap();
+    ///     child.resize(120, 40).unwrap();
+    ///     child.write_stdin(b"q\n").unwrap();
+    ///     while let Ok(event) = rx.recv() {
+    ///         if let CommandEvent::Stdout(data) = event {
+    ///             print!("{}", String::from_utf8_lossy(&data));
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn pty(&mut self, pty: bool) -> &mut Self {
+        self.pty = pty;
+        self
+    }
+
+    /// Write `input` to the standard input of the elevated process, which is then closed
+    /// 
+    /// This method is only applicable on `Linux`
+    /// 
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::Command;
+    /// use std::process::Command as StdCommand;
+    ///
+    /// fn main() {
+    ///     let mut cmd = StdCommand::new("tee");
+    ///     cmd.arg("/etc/example.conf");
+    ///     let mut elevated_cmd = Command::new(cmd);
+    ///     elevated_cmd.stdin_bytes(b"key = value\n".to_vec());
+    ///     let output = elevated_cmd.output().unwrap();
+    /// }
+    /// ```
+    pub fn stdin_bytes(&mut self, input: Vec<u8>) -> &mut Self {
+        self.stdin = Stdin::Bytes(input);
+        self
+    }
+
+    /// Keep the standard input of a spawned command open, to be written through
+    /// `CommandChild::stdin`, otherwise the elevated process reads nothing
+    /// 
+    /// This method is only applicable on `Linux`
+    pub fn stdin_piped(&mut self, piped: bool) -> &mut Self {
+        self.stdin = if piped { Stdin::Piped } else { Stdin::Null };
+        self
+    }
+
+    /// Set where the standard output of the elevated process goes, captured by default
+    /// 
+    /// Ignored in a pseudo-terminal. This method is only applicable on `Linux`
+    /// 
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::{Command, StreamMode};
+    /// use std::process::Command as StdCommand;
+    ///
+    /// fn main() {
+    ///     let mut cmd = StdCommand::new("journalctl");
+    ///     cmd.arg("--no-pager");
+    ///     let mut elevated_cmd = Command::new(cmd);
+    ///     elevated_cmd.stdout(StreamMode::File("journal.log".into()));
+    ///     elevated_cmd.stderr(StreamMode::Inherit);
+    ///     let output = elevated_cmd.output().unwrap();
+    /// }
+    /// ```
+    pub fn stdout(&mut self, mode: StreamMode) -> &mut Self {
+        self.stdout = mode;
+        self
+    }
+
+    /// Set where the standard error of the elevated process goes, captured by default
+    /// 
+    /// The error output is always read through a pipe, so a failed authentication is
+    /// still told apart from the messages of the elevating tool. It is then written where
+    /// `mode` says, the process never writing to the terminal itself
+    /// 
+    /// Ignored in a pseudo-terminal. This method is only applicable on `Linux`
+    pub fn stderr(&mut self, mode: StreamMode) -> &mut Self {
+        self.stderr = mode;
+        self
+    }
+
+    /// Execute the command through the given privileged helper, see [`crate::helper`]
+    ///
+    /// The helper is authorized, not the command: anyone allowed to use its action can
+    /// invoke it with any arguments, so it must only execute the commands it accepts
+    /// in [`crate::helper::run`]
+    /// 
+    /// The helper receives the environment variables as arguments, so `pkexec`
+    /// executes the helper itself rather than `env`. This method is only applicable on `Linux`
+    /// 
+    /// With the `polkit-dbus` feature the authorization is checked over D-Bus first, which
+    /// tells a dismissed dialog apart and supports `Command::cancellation`. As `pkexec`
+    /// authorizes the helper again, this is only done when the action of the helper uses
+    /// `auth_admin_keep` or `auth_self_keep` for active sessions, so the user is prompted once
+    pub fn helper(&mut self, helper: PathBuf) -> &mut Self {
+        self.helper = Some(helper);
+        self
     }
 
     /// Prompting the user with a graphical OS dialog for the root password, 
@@ -54,45 +272,998 @@ impl Command {
     /// }
     /// ```
     pub fn output(&self) -> Result<Output> {
-        let pkexec = PathBuf::from_str("/bin/pkexec")?;
-        let mut command = StdCommand::new(pkexec);
-        let display = env::var("DISPLAY");
-        let xauthority = env::var("XAUTHORITY");
-        let home = env::var("HOME");
-
-        command.arg("--disable-internal-agent");
-        if display.is_ok() || xauthority.is_ok() || home.is_ok() {
-            command.arg("env");
-            if let Ok(display) = display {
-                command.arg(format!("DISPLAY={}", display));
+        self.retry_auth(|| self.output_once())
+    }
+
+    fn output_once(&self) -> Result<Output> {
+        let mut turn = self.wait_turn()?;
+        let (backend, command) = self.prepare()?;
+        let started = start_check(backend, command.get_program());
+        let (output, head) = pipe::output_with(command, &self.stdin, &self.stdout, &self.stderr, |child| {
+            let watch = started.map(|started| StartWatch::new(child.id(), started, turn.take()));
+            let status = child.wait();
+            if let Some(watch) = watch {
+                watch.finish();
             }
-            if let Ok(xauthority) = xauthority {
-                command.arg(format!("XAUTHORITY={}", xauthority));
+            status
+        })?;
+        self.check_refused(backend, output.status, head)?;
+        Ok(output)
+    }
+
+    // Fail if the elevating tool refused to run the program, told from the start of
+    // its error output
+    fn check_refused(&self, backend: Backend, status: ExitStatus, stderr: Vec<u8>) -> Result<()> {
+        let output = Output {
+            status,
+            stdout: vec![],
+            stderr,
+        };
+        if self.non_interactive && prompt_refused(backend, &output) {
+            return Err(ElevationError::InteractionRequired.into());
+        }
+        if let Some(e) = auth_failure(backend, &output) {
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Execute the command with escalated privileges like `Command::output`, also
+    /// reporting how it was elevated, how long authenticating and running it took
+    /// and the resources it used
+    ///
+    /// The authentication is known to be over once `pkexec` or `doas` replaced
+    /// itself with the program, or `sudo` started it, which is checked every 10 ms.
+    /// It is unknown when the command is elevated on the host of a sandbox
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::Command;
+    /// use std::process::Command as StdCommand;
+    ///
+    /// fn main() {
+    ///     let cmd = StdCommand::new("path to the application");
+    ///     let elevated_cmd = Command::new(cmd);
+    ///     let output = elevated_cmd.output_details().unwrap();
+    ///     println!("authenticated in {:?}, ran for {:?} with {:?}",
+    ///         output.auth_wait, output.run_duration, output.backend);
+    ///     println!("used {:?} of CPU", output.usage.user_time + output.usage.system_time);
+    /// }
+    /// ```
+    pub fn output_details(&self) -> Result<ElevatedOutput> {
+        self.retry_auth(|| self.output_details_once())
+    }
+
+    fn output_details_once(&self) -> Result<ElevatedOutput> {
+        let mut turn = self.wait_turn()?;
+        let (backend, command) = self.prepare()?;
+        let started = start_check(backend, command.get_program());
+        let mut usage = ResourceUsage::default();
+        let mut times = None;
+        let (output, head) = pipe::output_with(command, &self.stdin, &self.stdout, &self.stderr, |child| {
+            let spawned = Instant::now();
+            let watch = started.map(|started| StartWatch::new(child.id(), started, turn.take()));
+            wait_exited(child.id())?;
+            let exited = Instant::now();
+            let started = match backend {
+                Backend::Direct => Some(spawned),
+                _ => watch.and_then(StartWatch::finish),
+            };
+            let status = wait_usage(child.id(), &mut usage);
+            times = Some((spawned, started, exited));
+            status
+        })?;
+        self.check_refused(backend, output.status, head)?;
+
+        let (spawned, started, exited) = times.ok_or(anyhow!("the process was not waited for"))?;
+        let program = self.cmd.get_program();
+        Ok(ElevatedOutput {
+            status: output.status,
+            stdout: output.stdout,
+            stderr: output.stderr,
+            auth_wait: started.map(|started| started - spawned),
+            run_duration: exited - started.unwrap_or(spawned),
+            backend,
+            program: resolve_program(Path::new(program)).unwrap_or_else(|_| PathBuf::from(program)),
+            user: "root".to_string(),
+            usage,
+        })
+    }
+
+    /// Execute the command with escalated privileges, waiting for it to finish and
+    /// returning its exit status
+    /// 
+    /// The elevated process uses the terminal of the current program for its input and output
+    /// 
+    /// A dismissed or denied `pkexec` prompt fails with `ElevationError::AuthDismissed` or
+    /// `ElevationError::AuthDenied` and is retried like `Command::output`. The messages of
+    /// `sudo` and `doas` go to the terminal, so a wrong password or a password required by
+    /// `Command::non_interactive` is only seen as their exit code 1
+    /// 
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::Command;
+    /// use std::process::Command as StdCommand;
+    ///
+    /// fn main() {
+    ///     let cmd = StdCommand::new("path to the application");
+    ///     let elevated_cmd = Command::new(cmd);
+    ///     let status = elevated_cmd.status().unwrap();
+    ///     println!("exited with {}", status);
+    /// }
+    /// ```
+    pub fn status(&self) -> Result<ExitStatus> {
+        self.retry_auth(|| self.status_once())
+    }
+
+    fn status_once(&self) -> Result<ExitStatus> {
+        let mut turn = self.wait_turn()?;
+        let (backend, mut command) = self.prepare()?;
+        let started = start_check(backend, command.get_program());
+        let mut child = command
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+        let watch = started.map(|started| StartWatch::new(child.id(), started, turn.take()));
+        // pkexec tells why it failed on the terminal, its exit code is only its own
+        // when it never executed the program
+        wait_exited(child.id())?;
+        let started = watch.map(|watch| watch.finish().is_some());
+        let refused = backend == Backend::Pkexec && started == Some(false);
+        let status = child.wait()?;
+        match status.code() {
+            Some(126) if refused => Err(ElevationError::AuthDismissed.into()),
+            Some(127) if refused => Err(ElevationError::AuthDenied.into()),
+            _ => Ok(status),
+        }
+    }
+
+    /// Execute with escalated privileges and stream output in real-time
+    /// 
+    /// Returns a channel receiver for CommandEvent messages and a CommandChild handle
+    /// 
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::{Command, CommandEvent};
+    /// use std::process::Command as StdCommand;
+    ///
+    /// fn main() {
+    ///     let mut cmd = StdCommand::new("path to the application");
+    ///     let elevated_cmd = Command::new(cmd);
+    ///     
+    ///     let (rx, child) = elevated_cmd.spawn().unwrap();
+    ///     
+    ///     while let Ok(event) = rx.recv() {
+    ///         match event {
+    ///             CommandEvent::Stdout(data) => {
+    ///                 println!("OUT: {}", String::from_utf8_lossy(&data));
+    ///             }
+    ///             CommandEvent::Stderr(data) => {
+    ///                 eprintln!("ERR: {}", String::from_utf8_lossy(&data));
+    ///             }
+    ///             CommandEvent::Terminated { code, .. } => {
+    ///                 println!("Process exited with code: {:?}", code);
+    ///                 break;
+    ///             }
+    ///             CommandEvent::Error(err) => {
+    ///                 eprintln!("Error: {}", err);
+    ///                 break;
+    ///             }
+    ///             CommandEvent::AuthRequested => {
+    ///                 println!("Waiting for authentication");
+    ///             }
+    ///             _ => {}
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn spawn(self) -> Result<(Receiver<CommandEvent>, CommandChild)> {
+        let (tx, rx) = event_channel(self.event_capacity, self.replay_buffer);
+        let child = self.spawn_events(tx)?;
+        Ok((rx, child))
+    }
+
+    // Spawn the command, reporting its events through `tx`
+    pub(crate) fn spawn_events(self, tx: EventSender) -> Result<CommandChild> {
+        let turn = self.wait_turn()?;
+        let (backend, command) = self.prepare()?;
+        let auth: Option<AuthCheck> = if self.expects_challenge(backend) {
+            Some(Box::new(move |output| auth_failure(backend, output).is_some()))
+        } else {
+            None
+        };
+        let launching = Launching {
+            turn,
+            auth,
+            started: start_check(backend, command.get_program()),
+        };
+        if self.pty {
+            return spawn_pty(command, launching, self.stdin, tx);
+        }
+        spawn_piped(command, launching, self.stdin, &self.stdout, &self.stderr, tx)
+    }
+
+    /// Check whether executing the command would be allowed right away, would show
+    /// the authentication dialog or would be denied, without prompting the user
+    /// 
+    /// The check is done with `pkcheck` for the same action and details `pkexec` uses
+    /// 
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::{AuthStatus, Command};
+    /// use std::process::Command as StdCommand;
+    ///
+    /// fn main() {
+    ///     let cmd = StdCommand::new("path to the application");
+    ///     let elevated_cmd = Command::new(cmd);
+    ///     if elevated_cmd.authorization_status().unwrap() == AuthStatus::ChallengeRequired {
+    ///         println!("this requires admin");
+    ///     }
+    /// }
+    /// ```
+    pub fn authorization_status(&self) -> Result<AuthStatus> {
+        match Command::backend()? {
+            Backend::Direct => Ok(AuthStatus::Authorized),
+            Backend::Sudo => sudo_status(),
+            Backend::Doas => doas_status(),
+            // Command::backend only picks among the Linux backends
+            _ => self.pkexec_status(),
+        }
+    }
+
+    /// List the ways to escalate privileges available on this system, without prompting the user
+    /// 
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::Command;
+    ///
+    /// fn main() {
+    ///     for availability in Command::can_elevate() {
+    ///         println!("{:?} {:?} graphical: {}", availability.backend,
+    ///             availability.version, availability.graphical_agent);
+    ///     }
+    /// }
+    /// ```
+    pub fn can_elevate() -> Vec<BackendAvailability> {
+        let mut backends = vec![];
+        if Command::is_elevated() {
+            backends.push(BackendAvailability {
+                backend: Backend::Direct,
+                version: None,
+                graphical_agent: false,
+                text_agent: false,
+                non_interactive: true,
+            });
+        }
+
+        if Path::new(PKEXEC).exists() {
+            let version = StdCommand::new(PKEXEC)
+                .arg("--version")
+                .output()
+                .ok()
+                .and_then(|output| version_of(&output.stdout));
+            let probe = Command::new(StdCommand::new("true"));
+            backends.push(BackendAvailability {
+                backend: Backend::Pkexec,
+                version,
+                graphical_agent: polkit_agent_running(),
+                // the internal textual agent is disabled while executing
+                text_agent: false,
+                non_interactive: probe.pkexec_status().ok() == Some(AuthStatus::Authorized),
+            });
+        }
+
+        if resolve_program(Path::new("sudo")).is_ok() {
+            let version = StdCommand::new("sudo")
+                .arg("-V")
+                .output()
+                .ok()
+                .and_then(|output| version_of(&output.stdout));
+            backends.push(BackendAvailability {
+                backend: Backend::Sudo,
+                version,
+                graphical_agent: env::var_os("SUDO_ASKPASS").is_some(),
+                text_agent: unsafe { libc::isatty(libc::STDIN_FILENO) } == 1,
+                non_interactive: sudo_status().ok() == Some(AuthStatus::Authorized),
+            });
+        }
+
+        if resolve_program(Path::new("doas")).is_ok() {
+            backends.push(BackendAvailability {
+                backend: Backend::Doas,
+                // doas does not report its version
+                version: None,
+                graphical_agent: false,
+                text_agent: unsafe { libc::isatty(libc::STDIN_FILENO) } == 1,
+                non_interactive: doas_status().ok() == Some(AuthStatus::Authorized),
+            });
+        }
+        backends
+    }
+
+    // Ask polkit about the action of the command, see `Command::authorization_status`
+    fn pkexec_status(&self) -> Result<AuthStatus> {
+        let details = self.pkexec_details()?;
+
+        #[cfg(feature = "polkit-dbus")]
+        {
+            let authorization = crate::dbus::check_authorization(self.get_action_id(), &details, false, "")?;
+            if authorization.is_authorized {
+                Ok(AuthStatus::Authorized)
+            } else if authorization.is_challenge {
+                Ok(AuthStatus::ChallengeRequired)
+            } else {
+                Ok(AuthStatus::Denied)
+            }
+        }
+
+        #[cfg(not(feature = "polkit-dbus"))]
+        {
+            let mut pkcheck = StdCommand::new("pkcheck");
+            pkcheck
+                .arg("--action-id")
+                .arg(self.get_action_id())
+                .arg("--process")
+                .arg(format!("{},{},{}", std::process::id(), process_start_time()?, unsafe { libc::getuid() }));
+            for (k, v) in details.iter() {
+                pkcheck.arg("--detail").arg(k).arg(v);
             }
-            if let Ok(home) = home {
-                command.arg(format!("HOME={}", home));
+            let output = pkcheck.output()?;
+
+            match output.status.code() {
+                Some(0) => Ok(AuthStatus::Authorized),
+                Some(1) => Ok(AuthStatus::Denied),
+                Some(2) | Some(3) => Ok(AuthStatus::ChallengeRequired),
+                _ => bail!("pkcheck failed: {}", String::from_utf8_lossy(&output.stderr).trim_end()),
             }
+        }
+    }
+
+    /// Show the authentication dialog for the polkit action of the command
+    /// if necessary, without executing it
+    /// 
+    /// Fails with `ElevationError::AuthDismissed` when the user dismissed the dialog,
+    /// `ElevationError::AuthDenied` when the user is not authorized and
+    /// `ElevationError::Cancelled` when `Command::cancellation` was triggered
+    /// 
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::Command;
+    /// use std::process::Command as StdCommand;
+    ///
+    /// fn main() {
+    ///     let cmd = StdCommand::new("path to the application");
+    ///     let mut elevated_cmd = Command::new(cmd);
+    ///     elevated_cmd.action_id("com.example.maintenance");
+    ///     elevated_cmd.authorize().unwrap();
+    /// }
+    /// ```
+    #[cfg(feature = "polkit-dbus")]
+    pub fn authorize(&self) -> Result<()> {
+        let details = self.pkexec_details()?;
+        let cancellation_id = self.cancellation.as_ref().map_or("", |c| c.id());
+        let authorization = crate::dbus::check_authorization(self.get_action_id(), &details, true, cancellation_id)?;
+        if authorization.is_authorized {
+            Ok(())
+        } else if authorization.is_dismissed() {
+            Err(ElevationError::AuthDismissed.into())
+        } else {
+            Err(ElevationError::AuthDenied.into())
+        }
+    }
+
+    /// Set the handle cancelling a pending authorization
+    /// 
+    /// The handle applies to `Command::authorize`, and to executing the command only
+    /// when it is authorized over D-Bus first, see `Command::helper`. Executing the command
+    /// otherwise fails, as the dialog shown by `pkexec`, `sudo` or `doas` cannot be cancelled.
+    /// This method is only applicable on `Linux` with the `polkit-dbus` feature
+    #[cfg(feature = "polkit-dbus")]
+    pub fn cancellation(&mut self, cancellation: Cancellation) -> &mut Self {
+        self.cancellation = Some(cancellation);
+        self
+    }
+
+    // The details `pkexec` would pass to polkit for the command, along with the custom ones
+    fn pkexec_details(&self) -> Result<Vec<(String, String)>> {
+        let args = self.pkexec_args()?;
+        let program = resolve_program(Path::new(&args[1]))?;
+        let command_line = std::iter::once(program.as_os_str())
+            .chain(args[2..].iter().map(OsString::as_os_str))
+            .map(|a| a.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let mut details = vec![
+            ("program".to_string(), program.to_string_lossy().into_owned()),
+            ("command_line".to_string(), command_line),
+        ];
+        details.extend(self.details.iter().cloned());
+        Ok(details)
+    }
+
+    /// Detect the container runtime, WSL and the application sandbox
+    /// the current program is running in
+    /// 
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::Command;
+    ///
+    /// fn main() {
+    ///     let environment = Command::environment();
+    ///     if environment.wsl {
+    ///         println!("running in WSL");
+    ///     }
+    /// }
+    /// ```
+    pub fn environment() -> Environment {
+        Environment {
+            container: detect_container(),
+            wsl: detect_wsl(),
+            sandbox: Command::sandbox(),
+        }
+    }
+
+    /// The way commands are going to be executed with escalated privileges,
+    /// following the environment the current program is running in:
+    /// 
+    /// * `Backend::Direct` when running as root already
+    /// * `Backend::Pkexec` in an application sandbox, elevating on the host
+    /// * `Backend::Sudo` or `Backend::Doas` in containers and WSL, which rarely run
+    ///   a polkit daemon, or when `pkexec` is missing
+    /// * `Backend::Pkexec` otherwise
+    /// 
+    /// Fails with `ElevationError::NoElevationAvailable` when no backend is installed
+    /// 
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::Command;
+    ///
+    /// fn main() {
+    ///     let backend = Command::backend().unwrap();
+    /// }
+    /// ```
+    pub fn backend() -> Result<Backend> {
+        if Command::is_elevated() {
+            return Ok(Backend::Direct);
+        }
+        let environment = Command::environment();
+        if environment.sandbox.is_some() {
+            return Ok(Backend::Pkexec);
+        }
+
+        let pkexec = Path::new(PKEXEC).exists();
+        let sudo = resolve_program(Path::new("sudo")).is_ok();
+        let doas = resolve_program(Path::new("doas")).is_ok();
+        if (environment.container.is_some() || environment.wsl) && (sudo || doas) {
+            Ok(if sudo { Backend::Sudo } else { Backend::Doas })
+        } else if pkexec {
+            Ok(Backend::Pkexec)
+        } else if sudo {
+            Ok(Backend::Sudo)
+        } else if doas {
+            Ok(Backend::Doas)
         } else {
-            if self.cmd.get_envs().any(|(_, v)| v.is_some()) {
-                command.arg("env");
+            Err(ElevationError::NoElevationAvailable.into())
+        }
+    }
+
+    /// Detect the application sandbox the current program is running in
+    /// 
+    /// Commands executed from a sandbox are elevated on the host instead, which fails
+    /// with `ElevationError::HostAccessDenied` when the sandbox does not allow it
+    /// 
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::{Command, Sandbox};
+    ///
+    /// fn main() {
+    ///     if Command::sandbox() == Some(Sandbox::Flatpak) {
+    ///         println!("running in a Flatpak");
+    ///     }
+    /// }
+    /// ```
+    pub fn sandbox() -> Option<Sandbox> {
+        if Path::new(FLATPAK_INFO).exists() {
+            Some(Sandbox::Flatpak)
+        } else if env::var_os("SNAP").is_some() {
+            Some(Sandbox::Snap)
+        } else {
+            None
+        }
+    }
+
+    // Pick the backend and build its invocation, making sure it is not going to prompt
+    // when running non-interactively
+    fn prepare(&self) -> Result<(Backend, StdCommand)> {
+        let backend = Command::backend()?;
+        if self.non_interactive {
+            let authorized = match backend {
+                Backend::Direct => true,
+                Backend::Sudo => sudo_allows(self.cmd.get_program(), self.cmd.get_args()),
+                // doas has no way to check a single command, `doas -n` fails fast instead
+                Backend::Doas => true,
+                _ => self.pkexec_status()? == AuthStatus::Authorized,
+            };
+            if !authorized {
+                return Err(ElevationError::InteractionRequired.into());
+            }
+        }
+
+        // pkexec authorizes the helper again, which only goes without a second prompt
+        // when polkit keeps the authentication, otherwise pkexec alone prompts the user
+        #[cfg(feature = "polkit-dbus")]
+        if self.helper.is_some()
+            && backend == Backend::Pkexec
+            && crate::dbus::retains_authorization(self.get_action_id())?
+        {
+            self.authorize()?;
+        } else if self.cancellation.is_some() && backend != Backend::Direct {
+            bail!("a cancellation needs a helper whose polkit action keeps the authorization");
+        }
+
+        Ok((backend, self.elevated_command(backend)?))
+    }
+
+    // Whether the user is going to be asked to authenticate, assuming so when unsure
+    fn expects_challenge(&self, backend: Backend) -> bool {
+        if backend == Backend::Direct || self.non_interactive {
+            return false;
+        }
+        !matches!(self.authorization_status(), Ok(AuthStatus::Authorized))
+    }
+
+    // Build the invocation executing the wrapped command with the given backend
+    fn elevated_command(&self, backend: Backend) -> Result<StdCommand> {
+        match backend {
+            Backend::Direct => {
+                let mut command = StdCommand::new(self.cmd.get_program());
+                command.args(self.cmd.get_args());
+                for (k, v) in self.cmd.get_envs() {
+                    if let Some(value) = v {
+                        command.env(k, value);
+                    }
+                }
+                Ok(command)
+            }
+            Backend::Sudo | Backend::Doas => {
+                let mut command = StdCommand::new(if backend == Backend::Sudo { "sudo" } else { "doas" });
+                if self.non_interactive {
+                    command.arg("-n");
+                }
+                // not through `env`, so the command line still matches a `SudoersRule` of
+                // the command. sudo sets the variables given as arguments, see `env_arg`,
+                // doas has no such arguments and only keeps them with `setenv`
+                for (k, v) in self.cmd.get_envs() {
+                    if let Some(value) = v {
+                        if backend == Backend::Sudo {
+                            command.arg(env_arg(k, value));
+                        } else {
+                            command.env(k, value);
+                        }
+                    }
+                }
+                command.arg("--");
+                command.arg(self.cmd.get_program());
+                command.args(self.cmd.get_args());
+                Ok(command)
+            }
+            _ => self.pkexec_command(),
+        }
+    }
+
+    // Build the `pkexec` invocation, going through the host when running in a sandbox
+    fn pkexec_command(&self) -> Result<StdCommand> {
+        let pkexec = PathBuf::from_str(PKEXEC)?;
+        let args = self.pkexec_args()?;
+
+        match Command::sandbox() {
+            Some(Sandbox::Flatpak) => {
+                check_flatpak_host_access()?;
+                let mut command = StdCommand::new("flatpak-spawn");
+                command.arg("--host").arg("--watch-bus");
+                for key in HOST_ENV {
+                    if let Ok(value) = env::var(key) {
+                        command.arg(format!("--env={}={}", key, value));
+                    }
+                }
+                command.arg(pkexec).args(args);
+                Ok(command)
+            }
+            Some(Sandbox::Snap) => {
+                check_snap_host_access()?;
+                let mut command = StdCommand::new(pkexec);
+                command.args(args);
+                Ok(command)
+            }
+            None => {
+                let mut command = StdCommand::new(pkexec);
+                command.args(args);
+                Ok(command)
+            }
+        }
+    }
+
+    // The arguments of `pkexec` running the wrapped command with its environment
+    fn pkexec_args(&self) -> Result<Vec<OsString>> {
+        let mut pkexec_args = vec![OsString::from("--disable-internal-agent")];
+
+        let mut envs = vec![];
+        for key in ["DISPLAY", "XAUTHORITY", "HOME"] {
+            if let Ok(value) = env::var(key) {
+                envs.push((OsString::from(key), OsString::from(value)));
             }
         }
         for (k, v) in self.cmd.get_envs() {
             if let Some(value) = v {
-                command.arg(format!("{}={}",
-                    k.to_str().ok_or(anyhow!("invalid key"))?,
-                    value.to_str().ok_or(anyhow!("invalid value"))?
-                ));
+                envs.push((k.to_os_string(), value.to_os_string()));
             }
         }
+        let program = self.cmd.get_program().to_os_string();
+        let args: Vec<OsString> = self.cmd.get_args().map(|a| a.to_os_string()).collect();
 
-        command.arg(self.cmd.get_program());
-        let args: Vec<&OsStr> = self.cmd.get_args().collect();
-        if !args.is_empty() {
-            command.args(args);
+        if let Some(ref helper) = self.helper {
+            pkexec_args.push(helper.as_os_str().to_os_string());
+            pkexec_args.extend(helper_args(&envs, &program, &args));
+            return Ok(pkexec_args);
         }
 
-        let output = command.output()?;
-        Ok(output)
+        if !envs.is_empty() {
+            pkexec_args.push(OsString::from("env"));
+        }
+        for (k, v) in envs {
+            pkexec_args.push(OsString::from(format!("{}={}",
+                k.to_str().ok_or(anyhow!("invalid key"))?,
+                v.to_str().ok_or(anyhow!("invalid value"))?
+            )));
+        }
+
+        pkexec_args.push(program);
+        pkexec_args.extend(args);
+
+        Ok(pkexec_args)
+    }
+}
+
+fn detect_container() -> Option<Container> {
+    if Path::new("/.dockerenv").exists() {
+        return Some(Container::Docker);
+    }
+    if Path::new("/run/.containerenv").exists() {
+        return Some(Container::Podman);
+    }
+    // set by systemd-nspawn, podman, lxc and others, see systemd's CONTAINER_INTERFACE
+    if let Ok(container) = env::var("container") {
+        return Some(match container.as_str() {
+            "docker" => Container::Docker,
+            "podman" => Container::Podman,
+            "lxc" | "lxc-libvirt" => Container::Lxc,
+            _ => Container::Other(container),
+        });
+    }
+    let cgroup = read_to_string("/proc/1/cgroup").unwrap_or_default();
+    if cgroup.contains("/docker") {
+        Some(Container::Docker)
+    } else if cgroup.contains("/libpod") {
+        Some(Container::Podman)
+    } else if cgroup.contains("/lxc") {
+        Some(Container::Lxc)
+    } else if cgroup.contains("/kubepods") || cgroup.contains("/containerd") {
+        Some(Container::Other("kubernetes".to_string()))
+    } else {
+        None
+    }
+}
+
+fn detect_wsl() -> bool {
+    if env::var_os("WSL_DISTRO_NAME").is_some() {
+        return true;
+    }
+    let release = read_to_string("/proc/sys/kernel/osrelease").unwrap_or_default().to_lowercase();
+    release.contains("microsoft") || release.contains("wsl")
+}
+
+// Take the version from the last word of the first line, like `pkexec version 0.105`
+fn version_of(output: &[u8]) -> Option<String> {
+    let output = String::from_utf8_lossy(output);
+    output.lines().next()?.split_whitespace().last().map(str::to_string)
+}
+
+// Look for a polkit authentication agent among the processes of the current user,
+// process names are truncated to 15 characters in /proc/<pid>/comm
+fn polkit_agent_running() -> bool {
+    let uid = unsafe { libc::getuid() };
+    let Ok(entries) = read_dir("/proc") else {
+        return false;
+    };
+    entries.flatten().any(|entry| {
+        if entry.metadata().map(|m| m.uid()).ok() != Some(uid) {
+            return false;
+        }
+        let comm = read_to_string(entry.path().join("comm")).unwrap_or_default();
+        let comm = comm.trim_end();
+        (comm.contains("polkit") && comm != "polkitd" && !comm.starts_with("polkit-agent-he"))
+            || comm.starts_with("lxqt-policykit")
+            || comm == "gnome-shell"
+    })
+}
+
+// Probe whether `doas` would run without asking for a password
+fn doas_status() -> Result<AuthStatus> {
+    let output = StdCommand::new("doas").args(["-n", "true"]).output()?;
+    if output.status.success() {
+        Ok(AuthStatus::Authorized)
+    } else {
+        Ok(AuthStatus::ChallengeRequired)
+    }
+}
+
+// Check whether sudoers lets the command run without a password, either through cached
+// credentials or a `NOPASSWD` rule, `sudo -n -l` fails rather than asking otherwise
+fn sudo_allows<'a>(program: &OsStr, args: impl Iterator<Item = &'a OsStr>) -> bool {
+    StdCommand::new("sudo")
+        .args(["-n", "-l", "--"])
+        .arg(program)
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+// Whether `sudo -n` or `doas -n` refused to run because a password would be needed
+fn prompt_refused(backend: Backend, output: &Output) -> bool {
+    if output.status.code() != Some(1) {
+        return false;
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    match backend {
+        Backend::Sudo => stderr.starts_with("sudo:") && stderr.contains("password is required"),
+        Backend::Doas => stderr.starts_with("doas:") && stderr.contains("Authentication required"),
+        _ => false,
+    }
+}
+
+// Tell a failed authentication apart from the command failing on its own, from the
+// exit code and the message of the elevating tool
+fn auth_failure(backend: Backend, output: &Output) -> Option<ElevationError> {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    match (backend, output.status.code()) {
+        (Backend::Pkexec, Some(code @ (126 | 127)))
+            if stderr.contains("Error executing command as another user") =>
+        {
+            if code == 126 {
+                Some(ElevationError::AuthDismissed)
+            } else {
+                Some(ElevationError::AuthDenied)
+            }
+        }
+        (Backend::Sudo, Some(1))
+            if stderr.starts_with("sudo:")
+                && (stderr.contains("incorrect password attempt") || stderr.contains("not in the sudoers file")) =>
+        {
+            Some(ElevationError::AuthDenied)
+        }
+        (Backend::Doas, Some(1))
+            if stderr.starts_with("doas:")
+                && (stderr.contains("Authentication failed") || stderr.contains("Operation not permitted")) =>
+        {
+            Some(ElevationError::AuthDenied)
+        }
+        _ => None,
+    }
+}
+
+// Probe whether `sudo` would run without asking for a password
+fn sudo_status() -> Result<AuthStatus> {
+    let output = StdCommand::new("sudo").args(["-n", "true"]).output()?;
+    if output.status.success() {
+        Ok(AuthStatus::Authorized)
+    } else {
+        Ok(AuthStatus::ChallengeRequired)
+    }
+}
+
+// Neither reaching the host from a Flatpak nor from a Snap is allowed by default,
+// tell the user which permission is missing rather than failing later on
+fn check_flatpak_host_access() -> Result<()> {
+    // the keyfile lists the bus names the sandbox may talk to, see flatpak-metadata(5)
+    let info = read_to_string(FLATPAK_INFO)?;
+    let mut section = "";
+    for line in info.lines().map(str::trim) {
+        if line.starts_with('[') && line.ends_with(']') {
+            section = &line[1..line.len() - 1];
+        } else if section == "Session Bus Policy" {
+            if let Some((name, policy)) = line.split_once('=') {
+                if name.trim() == "org.freedesktop.Flatpak" && ["talk", "own"].contains(&policy.trim()) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+    Err(ElevationError::HostAccessDenied(
+        "the Flatpak needs --talk-name=org.freedesktop.Flatpak to elevate on the host".to_string(),
+    ).into())
+}
+
+fn check_snap_host_access() -> Result<()> {
+    // only classic confinement sees the host file system, pkexec included
+    let snap = env::var("SNAP")?;
+    let meta = read_to_string(Path::new(&snap).join("meta").join("snap.yaml")).unwrap_or_default();
+    let classic = meta.lines().any(|line| {
+        line.split_once(':')
+            .is_some_and(|(key, value)| key.trim() == "confinement" && value.trim() == "classic")
+    });
+    if classic {
+        Ok(())
+    } else {
+        Err(ElevationError::HostAccessDenied(
+            "only Snaps with classic confinement can elevate on the host".to_string(),
+        ).into())
+    }
+}
+
+// Tells when the elevating process is done authenticating: `pkexec` and `doas` replace
+// themselves with the program, while `sudo` starts it as a child. `None` if it cannot
+// be followed, like on the host of a sandbox
+fn start_check(backend: Backend, tool: &OsStr) -> Option<StartCheck> {
+    let name = Path::new(tool).file_name()?.to_str()?.to_string();
+    match (backend, name.as_str()) {
+        (Backend::Direct, _) => Some(Box::new(|_| true)),
+        (Backend::Pkexec, "pkexec") | (Backend::Doas, "doas") => Some(exec_check(name)),
+        (Backend::Sudo, "sudo") => Some(Box::new(|pid| {
+            read_to_string(format!("/proc/{0}/task/{0}/children", pid)).is_ok_and(|c| !c.trim().is_empty())
+        })),
+        _ => None,
+    }
+}
+
+// Tells when the process stopped being the program `name`, which replaced itself
+fn exec_check(name: String) -> StartCheck {
+    Box::new(move |pid| read_to_string(format!("/proc/{}/comm", pid)).is_ok_and(|comm| comm.trim_end() != name))
+}
+
+// Notices when the program started, see `start_check`, then gives up the turn in the
+// elevation queue, as the prompt is over
+struct StartWatch {
+    pid: u32,
+    done: Arc<AtomicBool>,
+    // the check is handed back when the program was not seen starting
+    thread: JoinHandle<Result<Instant, StartCheck>>,
+}
+
+impl StartWatch {
+    fn new(pid: u32, started: StartCheck, turn: Option<Turn>) -> Self {
+        let done = Arc::new(AtomicBool::new(false));
+        let stop = done.clone();
+        let thread = thread::spawn(move || {
+            let _turn = turn;
+            while !stop.load(Ordering::SeqCst) {
+                if started(pid) {
+                    return Ok(Instant::now());
+                }
+                thread::sleep(START_POLL_INTERVAL);
+            }
+            Err(started)
+        });
+        Self { pid, done, thread }
+    }
+
+    // When the program started, checking once more as it may have exited between two
+    // checks. The process must not be reaped yet, see `wait_exited`
+    fn finish(self) -> Option<Instant> {
+        self.done.store(true, Ordering::SeqCst);
+        match self.thread.join().ok()? {
+            Ok(started) => Some(started),
+            Err(started) => started(self.pid).then(Instant::now),
+        }
+    }
+}
+
+// Wait for the process like `Child::wait`, also getting the resources used by it
+// and the children it waited for
+fn wait_usage(pid: u32, usage: &mut ResourceUsage) -> io::Result<ExitStatus> {
+    let mut status = 0;
+    let mut rusage: libc::rusage = unsafe { mem::zeroed() };
+    while unsafe { libc::wait4(pid as libc::pid_t, &mut status, 0, &mut rusage) } < 0 {
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+    *usage = ResourceUsage::from(&rusage);
+    Ok(ExitStatus::from_raw(status))
+}
+
+/// The start time of the current process in clock ticks since boot, which
+/// polkit uses along with the pid to guard against the pid being recycled
+pub(crate) fn process_start_time() -> Result<u64> {
+    let stat = read_to_string("/proc/self/stat")?;
+    let start_time = stat
+        .rsplit(')')
+        .next()
+        .and_then(|s| s.split_whitespace().nth(19))
+        .ok_or(anyhow!("invalid /proc/self/stat"))?;
+    Ok(start_time.parse()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ElevationQueue;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    #[test]
+    fn the_turn_is_released_once_the_program_started() {
+        // stands in for `pkexec`, replacing itself with the program once authenticated
+        let mut tool = StdCommand::new("sh");
+        tool.arg("-c").arg("sleep 0.3; exec sleep 2");
+        let first = ElevationQueue::wait().unwrap();
+        let mut child = tool.spawn().unwrap();
+        let watch = StartWatch::new(child.id(), exec_check("sh".to_string()), Some(first));
+
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            let second = ElevationQueue::wait();
+            let _ = tx.send(second.is_ok());
+        });
+        // still authenticating
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+        // the program runs, so the next command can prompt the user
+        assert_eq!(rx.recv_timeout(Duration::from_secs(1)), Ok(true));
+        assert!(child.try_wait().unwrap().is_none());
+
+        child.kill().unwrap();
+        wait_exited(child.id()).unwrap();
+        assert!(watch.finish().is_some());
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn a_program_exiting_between_two_checks_is_seen_starting() {
+        let mut tool = StdCommand::new("sh");
+        tool.arg("-c").arg("exec true");
+        let mut child = tool.spawn().unwrap();
+        let watch = StartWatch::new(child.id(), exec_check("sh".to_string()), None);
+        wait_exited(child.id()).unwrap();
+        assert!(watch.finish().is_some());
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn the_error_output_is_checked_whatever_its_mode() {
+        let mut tool = StdCommand::new("sh");
+        tool.arg("-c").arg("echo 'sudo: a password is required' >&2; exit 1");
+        let (output, head) =
+            pipe::output_with(tool, &Stdin::Null, &StreamMode::Capture, &StreamMode::Null, |child| child.wait())
+                .unwrap();
+        assert!(output.stderr.is_empty());
+
+        let mut cmd = Command::new(StdCommand::new("true"));
+        cmd.non_interactive(true);
+        let err = cmd.check_refused(Backend::Sudo, output.status, head).unwrap_err();
+        assert_eq!(err.downcast_ref::<ElevationError>(), Some(&ElevationError::InteractionRequired));
+    }
+
+    #[test]
+    fn a_reaped_process_is_not_killed() {
+        let mut child = StdCommand::new("sleep").arg("2").spawn().unwrap();
+        let process = pipe::Process::new(child.id());
+        process.kill().unwrap();
+        let status = process.wait(&mut child).unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGTERM));
+        // the pid may belong to another process by now
+        process.kill().unwrap();
     }
 }
@@ -0,0 +1,489 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Luis Liu. All rights reserved.
+ *  Licensed under the MIT License. See License in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use crate::backpressure::EventSender;
+use crate::CommandChild;
+use crate::CommandEvent;
+use crate::StreamMode;
+use crate::queue::Turn;
+use anyhow::Result;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Write};
+#[cfg(target_os = "linux")]
+use std::os::fd::OwnedFd;
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+#[cfg(target_os = "linux")]
+use std::process::ChildStdin;
+use std::process::{Child, Command as StdCommand, ExitStatus, Output, Stdio};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+// What the elevating process reads from its standard input
+#[derive(Clone)]
+pub(crate) enum Stdin {
+    Null,
+    // written at once, then closed
+    #[cfg(target_os = "linux")]
+    Bytes(Vec<u8>),
+    // written through `CommandChild::stdin`
+    #[cfg(target_os = "linux")]
+    Piped,
+}
+
+/// The input of an elevated process, see `CommandChild::stdin`
+///
+/// Dropping it, or calling `CommandStdin::close`, signals the end of the input
+pub struct CommandStdin {
+    pub(crate) file: File,
+    pty: bool,
+}
+
+impl CommandStdin {
+    #[cfg(target_os = "linux")]
+    pub(crate) fn new(file: File, pty: bool) -> Self {
+        Self { file, pty }
+    }
+
+    /// Signal the end of the input
+    ///
+    /// In a pseudo-terminal the end-of-file character is sent, as the terminal
+    /// stays open for the output
+    pub fn close(mut self) -> Result<()> {
+        if self.pty {
+            self.file.write_all(b"\x04")?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for CommandStdin {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+// Write the whole input in the background and close it, a process exiting
+// without reading all of it is not an error
+#[cfg(target_os = "linux")]
+fn feed(mut stdin: ChildStdin, input: Vec<u8>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let _ = stdin.write_all(&input);
+    })
+}
+
+// Where the output of a stream read by this process is written besides being captured
+type Copy = Box<dyn Write + Send>;
+
+// The standard output is only read by this process when it is captured, otherwise
+// the process writes it straight where its mode sends it
+fn stdout_stdio(mode: &StreamMode) -> Result<(Stdio, Option<Copy>)> {
+    Ok(match mode {
+        StreamMode::Capture => (Stdio::piped(), None),
+        StreamMode::Tee => (Stdio::piped(), Some(Box::new(io::stdout()))),
+        StreamMode::Inherit => (Stdio::inherit(), None),
+        StreamMode::Null => (Stdio::null(), None),
+        StreamMode::File(path) => (File::create(path)?.into(), None),
+    })
+}
+
+// The error output is always read by this process, as the messages of the elevating
+// tool tell a failed authentication apart, then handled as its mode says. Returns
+// whether it is captured and where it is copied
+fn stderr_route(mode: &StreamMode) -> Result<(bool, Option<Copy>)> {
+    Ok(match mode {
+        StreamMode::Capture => (true, None),
+        StreamMode::Tee => (true, Some(Box::new(io::stderr()))),
+        StreamMode::Inherit => (false, Some(Box::new(io::stderr()))),
+        StreamMode::Null => (false, None),
+        StreamMode::File(path) => (false, Some(Box::new(File::create(path)?))),
+    })
+}
+
+// Like `std::process::Command::output` with the given input and output streams, waiting
+// for the process with `wait` while its output is read. Also returns the start of the
+// error output, which is read whatever its mode
+#[cfg(target_os = "linux")]
+pub(crate) fn output_with(
+    mut command: StdCommand,
+    input: &Stdin,
+    stdout: &StreamMode,
+    stderr: &StreamMode,
+    wait: impl FnOnce(&mut Child) -> io::Result<ExitStatus>,
+) -> Result<(Output, Vec<u8>)> {
+    let stdin = match input {
+        Stdin::Bytes(_) => Stdio::piped(),
+        _ => Stdio::null(),
+    };
+    let (stdout_stdio, stdout_copy) = stdout_stdio(stdout)?;
+    let (stderr_captured, stderr_copy) = stderr_route(stderr)?;
+    command.stdin(stdin).stdout(stdout_stdio).stderr(Stdio::piped());
+    let mut child = command.spawn()?;
+    let writer = match (input, child.stdin.take()) {
+        (Stdin::Bytes(bytes), Some(stdin)) => Some(feed(stdin, bytes.clone())),
+        _ => None,
+    };
+    let stdout = child.stdout.take().map(|out| thread::spawn(move || collect(out, true, stdout_copy)));
+    let stderr = child
+        .stderr
+        .take()
+        .map(|err| thread::spawn(move || collect(err, stderr_captured, stderr_copy)));
+
+    let status = wait(&mut child)?;
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+    let join = |reader: Option<thread::JoinHandle<(Vec<u8>, Vec<u8>)>>| {
+        reader.and_then(|reader| reader.join().ok()).unwrap_or_default()
+    };
+    let (stdout, _) = join(stdout);
+    let (stderr, head) = join(stderr);
+    Ok((Output { status, stdout, stderr }, head))
+}
+
+// Read a stream to its end, returning what was captured and its first `MAX_HELD` bytes
+#[cfg(target_os = "linux")]
+fn collect(mut reader: impl Read, capture: bool, mut copy: Option<Copy>) -> (Vec<u8>, Vec<u8>) {
+    let mut collected = vec![];
+    let mut head = vec![];
+    let mut buffer = [0u8; 8192];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => {
+                if let Some(ref mut copy) = copy {
+                    let _ = copy.write_all(&buffer[..n]);
+                }
+                if capture {
+                    collected.extend_from_slice(&buffer[..n]);
+                }
+                let room = MAX_HELD.saturating_sub(head.len()).min(n);
+                head.extend_from_slice(&buffer[..room]);
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        }
+    }
+    (collected, head)
+}
+
+// Wait for the process to exit without reaping it, so its pid is not reused yet
+pub(crate) fn wait_exited(pid: u32) -> io::Result<()> {
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    while unsafe { libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, libc::WEXITED | libc::WNOWAIT) } < 0 {
+        let e = io::Error::last_os_error();
+        if e.kind() != ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+// The elevating process, shared by the thread waiting for it and `CommandChild::kill`.
+// It is only reaped with the lock held, so a pid which may have been reused by another
+// process is never signalled
+pub(crate) struct Process {
+    pid: u32,
+    reaped: Mutex<bool>,
+}
+
+impl Process {
+    pub(crate) fn new(pid: u32) -> Arc<Self> {
+        Arc::new(Self {
+            pid,
+            reaped: Mutex::new(false),
+        })
+    }
+
+    // Wait for `child`, the process itself, to exit and reap it
+    pub(crate) fn wait(&self, child: &mut Child) -> io::Result<ExitStatus> {
+        // on failure the lock is just held while waiting
+        let _ = wait_exited(self.pid);
+        let mut reaped = self.reaped.lock().unwrap_or_else(PoisonError::into_inner);
+        let status = child.wait();
+        *reaped = true;
+        status
+    }
+
+    // Send SIGTERM, unless the process was reaped already
+    pub(crate) fn kill(&self) -> io::Result<()> {
+        let reaped = self.reaped.lock().unwrap_or_else(PoisonError::into_inner);
+        if *reaped {
+            return Ok(());
+        }
+        if unsafe { libc::kill(self.pid as libc::pid_t, libc::SIGTERM) } != 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::ESRCH) {
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Tells from the exit status and the error output whether the elevating process
+// failed to authenticate the user
+pub(crate) type AuthCheck = Box<dyn Fn(&Output) -> bool + Send>;
+
+// Tells from the elevating process whether it started the program, which means the
+// user authenticated
+pub(crate) type StartCheck = Box<dyn Fn(u32) -> bool + Send>;
+
+// How often the elevating process is checked for having started the program
+pub(crate) const START_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// More error output than this is not the elevating tool refusing to run the program
+const MAX_HELD: usize = 64 * 1024;
+
+// How the elevating process is followed while it authenticates the user
+pub(crate) struct Launching {
+    // released once the program started or the elevating process exited, the
+    // prompt is over by then
+    pub(crate) turn: Option<Turn>,
+    // set when the user is expected to authenticate, see `AuthCheck`
+    pub(crate) auth: Option<AuthCheck>,
+    pub(crate) started: Option<StartCheck>,
+}
+
+// Until the program is known to run, its error output is held back, as it may be the
+// elevating tool refusing to run it
+pub(crate) struct Launch {
+    // the elevating process, reported in `CommandEvent::Started`
+    pid: u32,
+    // whether the error output is sent as events, or only held to be checked
+    stderr_events: bool,
+    // the auth events are expected and were not sent yet
+    pending: bool,
+    held: Vec<u8>,
+    turn: Option<Turn>,
+    started: bool,
+    exited: bool,
+}
+
+impl Launch {
+    pub(crate) fn new(pid: u32, turn: Option<Turn>, auth: bool, stderr_events: bool) -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self {
+            pid,
+            stderr_events,
+            pending: auth,
+            held: vec![],
+            turn,
+            started: false,
+            exited: false,
+        }))
+    }
+
+    // The program runs, so the user authenticated
+    pub(crate) fn start(&mut self, tx: &EventSender) {
+        if self.started || self.exited {
+            return;
+        }
+        self.turn = None;
+        self.settle(tx, true);
+    }
+
+    // The elevating process exited, `granted` tells whether the user had authenticated,
+    // in which case the program ran even if it was not seen starting
+    pub(crate) fn exit(&mut self, tx: &EventSender, granted: bool) {
+        self.turn = None;
+        if !self.started && !self.exited {
+            self.settle(tx, granted);
+        }
+        self.exited = true;
+    }
+
+    // Whether the user may still be asked to authenticate
+    pub(crate) fn pending(&self) -> bool {
+        self.pending && !self.started
+    }
+
+    // Report the authentication, then the start of the program
+    fn settle(&mut self, tx: &EventSender, granted: bool) {
+        if self.pending {
+            self.pending = false;
+            let _ = tx.send(if granted {
+                CommandEvent::AuthGranted
+            } else {
+                CommandEvent::AuthDenied
+            });
+        }
+        if granted {
+            self.started = true;
+            let _ = tx.send(CommandEvent::Started { pid: self.pid });
+        }
+        let held = std::mem::take(&mut self.held);
+        if self.stderr_events && !held.is_empty() {
+            let _ = tx.send(CommandEvent::Stderr(held));
+        }
+    }
+}
+
+// Check every `START_POLL_INTERVAL` whether the elevating process `pid` started the
+// program, until it did or exited
+pub(crate) fn watch_start(pid: u32, started: StartCheck, launch: Arc<Mutex<Launch>>, tx: EventSender) {
+    thread::spawn(move || loop {
+        {
+            let launch = launch.lock().unwrap_or_else(PoisonError::into_inner);
+            if launch.started || launch.exited {
+                break;
+            }
+        }
+        if started(pid) {
+            launch.lock().unwrap_or_else(PoisonError::into_inner).start(&tx);
+            break;
+        }
+        thread::sleep(START_POLL_INTERVAL);
+    });
+}
+
+// Spawn the elevating process with its output piped, forwarding it through the channel
+// as it arrives, and report the exit code once both streams are closed. The turn in the
+// elevation queue is held until the program started. When the user is expected to
+// authenticate, this is reported with the auth events, before `CommandEvent::Started`
+pub(crate) fn spawn_piped(
+    mut command: StdCommand,
+    launching: Launching,
+    input: Stdin,
+    stdout: &StreamMode,
+    stderr: &StreamMode,
+    tx: EventSender,
+) -> Result<CommandChild> {
+    let stdin = match input {
+        Stdin::Null => Stdio::null(),
+        #[cfg(target_os = "linux")]
+        Stdin::Bytes(_) | Stdin::Piped => Stdio::piped(),
+    };
+    let (stdout_stdio, stdout_copy) = stdout_stdio(stdout)?;
+    let (stderr_captured, stderr_copy) = stderr_route(stderr)?;
+    command.stdin(stdin).stdout(stdout_stdio).stderr(Stdio::piped());
+    let mut child = command.spawn()?;
+    let stdin = match (input, child.stdin.take()) {
+        #[cfg(target_os = "linux")]
+        (Stdin::Bytes(bytes), Some(stdin)) => {
+            feed(stdin, bytes);
+            None
+        }
+        #[cfg(target_os = "linux")]
+        (Stdin::Piped, Some(stdin)) => Some(CommandStdin::new(File::from(OwnedFd::from(stdin)), false)),
+        _ => None,
+    };
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+    let pid = child.id();
+
+    let Launching { turn, auth, started } = launching;
+    if auth.is_some() {
+        let _ = tx.send(CommandEvent::AuthRequested);
+    }
+    let launch = Launch::new(pid, turn, auth.is_some(), stderr_captured);
+    if let Some(started) = started {
+        watch_start(pid, started, launch.clone(), tx.clone());
+    }
+
+    let broadcast = tx.broadcast();
+    let stdout_tx = tx.clone();
+    let stdout_launch = launch.clone();
+    let stdout_reader = thread::spawn(move || {
+        if let Some(stdout) = stdout {
+            forward(stdout, stdout_tx, CommandEvent::Stdout, true, stdout_copy, &stdout_launch, false);
+        }
+    });
+    let stderr_tx = tx.clone();
+    let stderr_launch = launch.clone();
+    let stderr_reader = thread::spawn(move || {
+        if let Some(stderr) = stderr {
+            forward(stderr, stderr_tx, CommandEvent::Stderr, stderr_captured, stderr_copy, &stderr_launch, true);
+        }
+    });
+
+    let process = Process::new(pid);
+    let waiter = process.clone();
+    thread::spawn(move || {
+        let _ = stdout_reader.join();
+        let _ = stderr_reader.join();
+        let status = waiter.wait(&mut child);
+        {
+            let mut launch = launch.lock().unwrap_or_else(PoisonError::into_inner);
+            let denied = match (&auth, &status) {
+                (Some(check), Ok(status)) if launch.pending() => check(&Output {
+                    status: *status,
+                    stdout: vec![],
+                    stderr: launch.held.clone(),
+                }),
+                _ => false,
+            };
+            launch.exit(&tx, !denied);
+        }
+        match status {
+            Ok(status) => {
+                let _ = tx.send(CommandEvent::Terminated {
+                    code: status.code(),
+                    signal: status.signal(),
+                    dropped: 0,
+                });
+            }
+            Err(e) => {
+                let _ = tx.send(CommandEvent::Error(e.to_string()));
+            }
+        }
+    });
+
+    Ok(CommandChild {
+        _output_dir: PathBuf::new(),
+        process: Some(process),
+        stdin,
+        #[cfg(target_os = "linux")]
+        pty: None,
+        broadcast,
+    })
+}
+
+// `hold` is set for the error output, which is held back while authenticating. Output
+// on the standard output shows the program runs. Output not `captured` is only copied,
+// the error output still being held to be checked
+fn forward(
+    mut reader: impl Read,
+    tx: EventSender,
+    event: fn(Vec<u8>) -> CommandEvent,
+    captured: bool,
+    mut copy: Option<Copy>,
+    launch: &Mutex<Launch>,
+    hold: bool,
+) {
+    let mut buffer = [0u8; 8192];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => {
+                if let Some(ref mut copy) = copy {
+                    let _ = copy.write_all(&buffer[..n]);
+                }
+                {
+                    let mut launch = launch.lock().unwrap_or_else(PoisonError::into_inner);
+                    if hold && launch.pending() {
+                        launch.held.extend_from_slice(&buffer[..n]);
+                        if launch.held.len() > MAX_HELD {
+                            launch.start(&tx);
+                        }
+                        continue;
+                    }
+                    if !hold {
+                        launch.start(&tx);
+                    }
+                }
+                if captured && tx.send(event(buffer[..n].to_vec())).is_err() {
+                    break;
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        }
+    }
+}
@@ -0,0 +1,295 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Luis Liu. All rights reserved.
+ *  Licensed under the MIT License. See License in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+//! Generate polkit JavaScript `.rules` files pre-authorizing an action for a group
+//!
+//! Granting [`PolkitResult::Yes`] for the action `Command` runs under lets the members
+//! of the group elevate without being prompted, see [`PolkitRule::for_command`]
+use crate::install::{install_file, uninstall_file};
+use crate::Command;
+use anyhow::{bail, Result};
+use std::fmt::Write;
+use std::path::PathBuf;
+
+/// The polkit action `pkexec` checks for when running an arbitrary program
+pub const PKEXEC_ACTION_ID: &str = "org.freedesktop.policykit.exec";
+
+/// The directory polkit loads local rules from
+pub const RULES_DIR: &str = "/etc/polkit-1/rules.d";
+
+/// The result a generated rule returns for a matching request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolkitResult {
+    /// Authorized without any prompt
+    Yes,
+    /// The user authenticates as themselves once, then stays authorized for a while
+    AuthSelfKeep,
+}
+
+impl PolkitResult {
+    fn as_js(&self) -> &'static str {
+        match self {
+            PolkitResult::Yes => "polkit.Result.YES",
+            PolkitResult::AuthSelfKeep => "polkit.Result.AUTH_SELF_KEEP",
+        }
+    }
+}
+
+/// A polkit rule returning a fixed result when a member of `group` requests `action_id`
+///
+/// # Examples
+///
+/// ```no_run
+/// use elevated_command::polkit::{PolkitResult, PolkitRule};
+///
+/// fn main() {
+///     let mut rule = PolkitRule::new("com.example.maintenance", "devops");
+///     rule.result(PolkitResult::Yes).local(true).active(true);
+///     rule.install("com.example.maintenance").unwrap();
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct PolkitRule {
+    action_id: String,
+    group: String,
+    result: PolkitResult,
+    priority: u8,
+    user: Option<String>,
+    local: bool,
+    active: bool,
+    program: Option<PathBuf>,
+}
+
+impl PolkitRule {
+    /// Constructs a rule granting `PolkitResult::AuthSelfKeep` for `action_id` to the members of `group`
+    pub fn new(action_id: &str, group: &str) -> Self {
+        Self {
+            action_id: action_id.to_string(),
+            group: group.to_string(),
+            result: PolkitResult::AuthSelfKeep,
+            priority: 50,
+            user: None,
+            local: false,
+            active: false,
+            program: None,
+        }
+    }
+
+    /// Constructs a rule for the action id the given `Command` is elevated under
+    ///
+    /// Be aware that a rule returning `PolkitResult::Yes` for [`PKEXEC_ACTION_ID`]
+    /// lets the group run any program as root without a password,
+    /// narrow it down with [`PolkitRule::program`] where possible
+    pub fn for_command(cmd: &Command, group: &str) -> Self {
+        Self::new(cmd.get_action_id(), group)
+    }
+
+    /// Set the result returned for matching requests
+    pub fn result(&mut self, result: PolkitResult) -> &mut Self {
+        self.result = result;
+        self
+    }
+
+    /// Set the priority used as the prefix of the file name, rules in files
+    /// sorting first take precedence. Defaults to `50`
+    pub fn priority(&mut self, priority: u8) -> &mut Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Only match requests from the given user, on top of the group membership
+    pub fn user(&mut self, user: &str) -> &mut Self {
+        self.user = Some(user.to_string());
+        self
+    }
+
+    /// Only match requests from a local session
+    pub fn local(&mut self, local: bool) -> &mut Self {
+        self.local = local;
+        self
+    }
+
+    /// Only match requests from the active session
+    pub fn active(&mut self, active: bool) -> &mut Self {
+        self.active = active;
+        self
+    }
+
+    /// Only match requests whose `program` detail is exactly the given absolute path
+    ///
+    /// `pkexec` reports the first program it executes, which is `/usr/bin/env`
+    /// whenever `Command` has to forward environment variables
+    pub fn program(&mut self, program: PathBuf) -> &mut Self {
+        self.program = Some(program);
+        self
+    }
+
+    /// Check the action id, names and program the rule is built from are valid, so they
+    /// are safe to embed in the generated script
+    ///
+    /// The script itself is not parsed: it only embeds these values as quoted strings
+    pub fn check_values(&self) -> Result<()> {
+        let parts: Vec<&str> = self.action_id.split('.').collect();
+        if parts.len() < 2 || parts.iter().any(|p| {
+            p.is_empty() || !p.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        }) {
+            bail!("invalid action id: {:?}", self.action_id);
+        }
+        if !is_valid_name(&self.group) {
+            bail!("invalid group name: {:?}", self.group);
+        }
+        if let Some(ref user) = self.user {
+            if !is_valid_name(user) {
+                bail!("invalid user name: {:?}", user);
+            }
+        }
+        if let Some(ref program) = self.program {
+            if !program.is_absolute() || program.to_str().is_none() {
+                bail!("program must be an absolute UTF-8 path: {:?}", program);
+            }
+        }
+        Ok(())
+    }
+
+    /// Generate the content of the `.rules` file
+    pub fn render(&self) -> Result<String> {
+        self.check_values()?;
+
+        let mut conditions = vec![
+            format!("action.id == {}", js_string(&self.action_id)),
+            format!("subject.isInGroup({})", js_string(&self.group)),
+        ];
+        if let Some(ref user) = self.user {
+            conditions.push(format!("subject.user == {}", js_string(user)));
+        }
+        if self.local {
+            conditions.push("subject.local".to_string());
+        }
+        if self.active {
+            conditions.push("subject.active".to_string());
+        }
+        if let Some(ref program) = self.program {
+            // checked to be valid UTF-8 by check_values()
+            let program = program.to_str().unwrap_or_default();
+            conditions.push(format!("action.lookup(\"program\") == {}", js_string(program)));
+        }
+
+        let mut rules = String::new();
+        writeln!(rules, "// Generated by elevated-command, do not edit")?;
+        writeln!(rules, "polkit.addRule(function(action, subject) {{")?;
+        writeln!(rules, "    if ({}) {{", conditions.join(" &&\n        "))?;
+        writeln!(rules, "        return {};", self.result.as_js())?;
+        writeln!(rules, "    }}")?;
+        writeln!(rules, "}});")?;
+        Ok(rules)
+    }
+
+    /// The path the rule is installed to under the given `name`
+    pub fn path(&self, name: &str) -> Result<PathBuf> {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.') {
+            bail!("invalid rules file name: {:?}", name);
+        }
+        Ok(PathBuf::from(RULES_DIR).join(format!("{:02}-{}.rules", self.priority, name)))
+    }
+
+    /// Install the rule as `RULES_DIR/<priority>-<name>.rules`, prompting for elevation
+    pub fn install(&self, name: &str) -> Result<()> {
+        let path = self.path(name)?;
+        let rules = self.render()?;
+        install_file(rules.as_bytes(), &path, "0644", None)
+    }
+
+    /// Remove the rule previously installed under `name`, prompting for elevation
+    pub fn uninstall(&self, name: &str) -> Result<()> {
+        let path = self.path(name)?;
+        uninstall_file(&path)
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+fn js_string(s: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            // U+2028 and U+2029 end a line in JavaScript, breaking the string literal
+            c if c.is_control() || c == '\u{2028}' || c == '\u{2029}' => {
+                escaped.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn js_string_escapes_quotes_and_line_terminators() {
+        assert_eq!(js_string("plain"), r#""plain""#);
+        assert_eq!(js_string(r#"a"b\c"#), r#""a\"b\\c""#);
+        assert_eq!(js_string("a\nb\rc\u{7}"), r#""a\nb\rc\u0007""#);
+        assert_eq!(js_string("a\u{2028}b\u{2029}"), r#""a\u2028b\u2029""#);
+        assert_eq!(js_string("é"), "\"é\"");
+    }
+
+    #[test]
+    fn render_generates_the_rule() {
+        let mut rule = PolkitRule::new("com.example.maintenance", "devops");
+        rule.result(PolkitResult::Yes)
+            .user("alice")
+            .local(true)
+            .active(true)
+            .program(PathBuf::from("/usr/bin/with \"quote\""));
+        assert_eq!(
+            rule.render().unwrap(),
+            r#"// Generated by elevated-command, do not edit
+polkit.addRule(function(action, subject) {
+    if (action.id == "com.example.maintenance" &&
+        subject.isInGroup("devops") &&
+        subject.user == "alice" &&
+        subject.local &&
+        subject.active &&
+        action.lookup("program") == "/usr/bin/with \"quote\"") {
+        return polkit.Result.YES;
+    }
+});
+"#
+        );
+    }
+
+    #[test]
+    fn render_defaults_to_auth_self_keep() {
+        let rules = PolkitRule::new("com.example.maintenance", "devops").render().unwrap();
+        assert!(rules.contains("    if (action.id == \"com.example.maintenance\" &&\n        subject.isInGroup(\"devops\")) {"));
+        assert!(rules.contains("return polkit.Result.AUTH_SELF_KEEP;"));
+    }
+
+    #[test]
+    fn render_rejects_values_unsafe_to_embed() {
+        assert!(PolkitRule::new("com.example\"); evil(); \"", "devops").render().is_err());
+        assert!(PolkitRule::new("single", "devops").render().is_err());
+        assert!(PolkitRule::new("com.example.maintenance", "dev\"ops").render().is_err());
+        assert!(PolkitRule::new("com.example.maintenance", "devops").user("-alice").render().is_err());
+        assert!(PolkitRule::new("com.example.maintenance", "devops")
+            .program(PathBuf::from("relative/path"))
+            .render()
+            .is_err());
+    }
+}
@@ -0,0 +1,167 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Luis Liu. All rights reserved.
+ *  Licensed under the MIT License. See License in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use crate::backpressure::EventSender;
+use crate::pipe::{watch_start, CommandStdin, Launch, Launching, Process, Stdin};
+use crate::CommandChild;
+use crate::CommandEvent;
+use anyhow::Result;
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::PathBuf;
+use std::process::{Command as StdCommand, Output};
+use std::sync::PoisonError;
+use std::thread;
+
+// The size of the terminal until `CommandChild::resize` is called
+const COLS: u16 = 80;
+const ROWS: u16 = 24;
+// How much of the output is kept to check why the elevating process exited
+const TAIL_LENGTH: usize = 4096;
+
+// Spawn the elevating process as the session leader of a new pseudo-terminal, so it
+// and the elevated process see a terminal on all standard streams. What they write
+// is forwarded as `CommandEvent::Stdout`, since a terminal does not tell the streams
+// apart. A password prompt of the elevating tool shows up there too, so whether the
+// user authenticated is only told from the elevating process
+pub(crate) fn spawn_pty(
+    mut command: StdCommand,
+    launching: Launching,
+    input: Stdin,
+    tx: EventSender,
+) -> Result<CommandChild> {
+    let (master, slave) = open()?;
+    command
+        .stdin(slave.try_clone()?)
+        .stdout(slave.try_clone()?)
+        .stderr(slave);
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                return Err(Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut child = command.spawn()?;
+    // close our copies of the terminal, so reading it ends once the process exits
+    drop(command);
+    let pid = child.id();
+    if let Stdin::Bytes(bytes) = input {
+        let mut writer = master.try_clone()?;
+        thread::spawn(move || {
+            let _ = writer.write_all(&bytes);
+        });
+    }
+
+    let Launching { turn, auth, started } = launching;
+    if auth.is_some() {
+        let _ = tx.send(CommandEvent::AuthRequested);
+    }
+    // the terminal has no separate error output to hold
+    let launch = Launch::new(pid, turn, auth.is_some(), false);
+    if let Some(started) = started {
+        watch_start(pid, started, launch.clone(), tx.clone());
+    }
+    let broadcast = tx.broadcast();
+    let process = Process::new(pid);
+    let waiter = process.clone();
+    let mut reader = master.try_clone()?;
+    thread::spawn(move || {
+        let mut buffer = [0u8; 8192];
+        // the last output, telling a refusal of the elevating tool apart at exit
+        let mut tail = vec![];
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => {
+                    tail.extend_from_slice(&buffer[..n]);
+                    tail.drain(..tail.len().saturating_sub(TAIL_LENGTH));
+                    if tx.send(CommandEvent::Stdout(buffer[..n].to_vec())).is_err() {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                // EIO once the last process using the terminal closed it
+                Err(_) => break,
+            }
+        }
+        let status = waiter.wait(&mut child);
+        {
+            let mut launch = launch.lock().unwrap_or_else(PoisonError::into_inner);
+            let denied = match (&auth, &status) {
+                (Some(check), Ok(status)) if launch.pending() => check(&Output {
+                    status: *status,
+                    stdout: vec![],
+                    stderr: tail,
+                }),
+                _ => false,
+            };
+            launch.exit(&tx, !denied);
+        }
+        match status {
+            Ok(status) => {
+                let _ = tx.send(CommandEvent::Terminated {
+                    code: status.code(),
+                    signal: status.signal(),
+                    dropped: 0,
+                });
+            }
+            Err(e) => {
+                let _ = tx.send(CommandEvent::Error(e.to_string()));
+            }
+        }
+    });
+
+    Ok(CommandChild {
+        _output_dir: PathBuf::new(),
+        process: Some(process),
+        stdin: Some(CommandStdin::new(master.try_clone()?, true)),
+        pty: Some(master),
+        broadcast,
+    })
+}
+
+// Open a new pseudo-terminal, returning its master and slave sides
+fn open() -> Result<(File, File)> {
+    let fd = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return Err(Error::last_os_error().into());
+    }
+    let master = unsafe { File::from_raw_fd(fd) };
+    if unsafe { libc::grantpt(fd) } != 0 || unsafe { libc::unlockpt(fd) } != 0 {
+        return Err(Error::last_os_error().into());
+    }
+    let mut name = [0 as libc::c_char; 64];
+    if unsafe { libc::ptsname_r(fd, name.as_mut_ptr(), name.len()) } != 0 {
+        return Err(Error::last_os_error().into());
+    }
+    let name = unsafe { CStr::from_ptr(name.as_ptr()) };
+    resize(&master, COLS, ROWS)?;
+
+    let slave = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(name.to_str()?)?;
+    Ok((master, slave))
+}
+
+pub(crate) fn resize(master: &File, cols: u16, rows: u16) -> Result<()> {
+    let size = libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    if unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size) } != 0 {
+        return Err(Error::last_os_error().into());
+    }
+    Ok(())
+}
@@ -0,0 +1,234 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Luis Liu. All rights reserved.
+ *  Licensed under the MIT License. See License in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use crate::Command;
+use crate::ElevationError;
+use anyhow::Result;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+/// The order in which concurrent elevation requests get their turn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueOrder {
+    /// Requests are served in the order they were made
+    Fifo,
+    /// Whichever waiting request wakes up first is served
+    Any,
+}
+
+/// Process-wide queue making concurrent elevation requests show their dialog one at a time
+///
+/// Every `Command::output` and `Command::spawn` which may prompt the user waits for its
+/// turn, commands running non-interactively or from an elevated program are not queued.
+/// On Linux a turn lasts until the user authenticated and the program started, or until
+/// the elevating tool exited when it is not known to have started. On MacOS and Windows
+/// it lasts until the output is collected, or until a spawned command terminates.
+///
+/// Optionally a lock file shared by all processes of the user serializes requests made
+/// by other programs using `elevated-command` as well
+///
+/// # Examples
+///
+/// ```no_run
+/// use elevated_command::{Command, ElevationQueue, QueueOrder};
+/// use std::process::Command as StdCommand;
+/// use std::thread;
+///
+/// fn main() {
+///     ElevationQueue::set_order(QueueOrder::Fifo);
+///     ElevationQueue::set_system_lock(true);
+///
+///     let handles: Vec<_> = ["first", "second"]
+///         .into_iter()
+///         .map(|name| {
+///             thread::spawn(move || {
+///                 let mut cmd = StdCommand::new("path to the application");
+///                 cmd.arg(name);
+///                 Command::new(cmd).output()
+///             })
+///         })
+///         .collect();
+///     for handle in handles {
+///         let _ = handle.join().unwrap();
+///     }
+/// }
+/// ```
+pub struct ElevationQueue;
+
+struct State {
+    order: QueueOrder,
+    system_lock: bool,
+    busy: bool,
+    waiting: VecDeque<u64>,
+    next: u64,
+    generation: u64,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    order: QueueOrder::Fifo,
+    system_lock: false,
+    busy: false,
+    waiting: VecDeque::new(),
+    next: 0,
+    generation: 0,
+});
+static WAKE: Condvar = Condvar::new();
+
+// How often the lock file shared with other processes is retried
+const LOCK_RETRY: Duration = Duration::from_millis(100);
+
+fn state() -> MutexGuard<'static, State> {
+    STATE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl ElevationQueue {
+    /// Set the order in which waiting requests are served, `QueueOrder::Fifo` by default
+    pub fn set_order(order: QueueOrder) {
+        state().order = order;
+        WAKE.notify_all();
+    }
+
+    /// Also wait for the lock file shared by all processes of the current user,
+    /// disabled by default
+    pub fn set_system_lock(enabled: bool) {
+        state().system_lock = enabled;
+    }
+
+    /// The lock file shared by all processes of the current user
+    pub fn system_lock_path() -> PathBuf {
+        #[cfg(target_family = "unix")]
+        let name = format!("elevated-command-{}.lock", unsafe { libc::getuid() });
+        #[cfg(not(target_family = "unix"))]
+        let name = "elevated-command.lock".to_string();
+        std::env::temp_dir().join(name)
+    }
+
+    /// The number of requests waiting for their turn
+    pub fn pending() -> usize {
+        state().waiting.len()
+    }
+
+    /// Cancel every request waiting for its turn, which fail with `ElevationError::Cancelled`
+    ///
+    /// The request currently showing its dialog is not affected
+    pub fn cancel_all() {
+        let mut state = state();
+        state.generation += 1;
+        state.waiting.clear();
+        WAKE.notify_all();
+    }
+
+    // Block until no other request of this process is being served, then take the
+    // lock file if enabled
+    pub(crate) fn wait() -> Result<Turn> {
+        let mut state = state();
+        let ticket = state.next;
+        let generation = state.generation;
+        state.next += 1;
+        state.waiting.push_back(ticket);
+        loop {
+            if state.generation != generation {
+                return Err(ElevationError::Cancelled.into());
+            }
+            let first = state.order == QueueOrder::Any || state.waiting.front() == Some(&ticket);
+            if !state.busy && first {
+                state.waiting.retain(|&t| t != ticket);
+                state.busy = true;
+                break;
+            }
+            state = WAKE.wait(state).unwrap_or_else(PoisonError::into_inner);
+        }
+        let system_lock = state.system_lock;
+        drop(state);
+
+        let mut turn = Turn { _file: None };
+        if system_lock {
+            turn._file = Some(lock_file(generation)?);
+        }
+        Ok(turn)
+    }
+}
+
+// Wait for the lock file, giving up when the waiting requests are cancelled
+fn lock_file(generation: u64) -> Result<File> {
+    let path = ElevationQueue::system_lock_path();
+    loop {
+        if let Some(file) = try_lock_file(&path)? {
+            return Ok(file);
+        }
+        if state().generation != generation {
+            return Err(ElevationError::Cancelled.into());
+        }
+        thread::sleep(LOCK_RETRY);
+    }
+}
+
+// Open the lock file and lock it, `None` if another process holds the lock
+#[cfg(target_family = "unix")]
+fn try_lock_file(path: &Path) -> io::Result<Option<File>> {
+    use std::io::ErrorKind;
+    use std::os::fd::AsRawFd;
+
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(Some(file));
+    }
+    match io::Error::last_os_error() {
+        e if e.kind() == ErrorKind::WouldBlock => Ok(None),
+        e => Err(e),
+    }
+}
+
+// Without sharing, opening the file fails while another process has it open
+#[cfg(windows)]
+fn try_lock_file(path: &Path) -> io::Result<Option<File>> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    // ERROR_SHARING_VIOLATION
+    const SHARING_VIOLATION: i32 = 32;
+    match OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .share_mode(0)
+        .open(path)
+    {
+        Ok(file) => Ok(Some(file)),
+        Err(e) if e.raw_os_error() == Some(SHARING_VIOLATION) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// The turn of a request in the elevation queue, the next one is served once dropped
+pub(crate) struct Turn {
+    // closing the file releases the lock
+    _file: Option<File>,
+}
+
+impl Drop for Turn {
+    fn drop(&mut self) {
+        state().busy = false;
+        WAKE.notify_all();
+    }
+}
+
+impl Command {
+    // Wait for the turn of a request which may prompt the user
+    pub(crate) fn wait_turn(&self) -> Result<Option<Turn>> {
+        if self.non_interactive || Command::is_elevated() {
+            return Ok(None);
+        }
+        ElevationQueue::wait().map(Some)
+    }
+}
@@ -0,0 +1,102 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Luis Liu. All rights reserved.
+ *  Licensed under the MIT License. See License in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use crate::Command;
+use crate::ElevationError;
+use anyhow::Result;
+use std::sync::{Mutex, PoisonError};
+
+/// Whether to prompt the user again after a failed authentication,
+/// see `Command::on_auth_failure`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryDecision {
+    Retry,
+    GiveUp,
+}
+
+pub(crate) type AuthFailureHandler = Mutex<Box<dyn FnMut(usize, &ElevationError) -> RetryDecision + Send>>;
+
+impl Command {
+    /// Prompt the user up to `retries` more times when `Command::output` fails to
+    /// authenticate, because the dialog was dismissed or the password was wrong
+    ///
+    /// A command which was executed and failed on its own is never retried
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::Command;
+    /// use std::process::Command as StdCommand;
+    ///
+    /// fn main() {
+    ///     let cmd = StdCommand::new("path to the application");
+    ///     let mut elevated_cmd = Command::new(cmd);
+    ///     elevated_cmd.auth_retries(2);
+    ///     let output = elevated_cmd.output().unwrap();
+    /// }
+    /// ```
+    pub fn auth_retries(&mut self, retries: usize) -> &mut Self {
+        self.auth_retries = retries;
+        self
+    }
+
+    /// Decide whether to prompt again after a failed authentication, as long as
+    /// `Command::auth_retries` allows it
+    ///
+    /// The handler gets the number of failed attempts so far and why the last one failed
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::{Command, ElevationError, RetryDecision};
+    /// use std::process::Command as StdCommand;
+    ///
+    /// fn main() {
+    ///     let cmd = StdCommand::new("path to the application");
+    ///     let mut elevated_cmd = Command::new(cmd);
+    ///     elevated_cmd.auth_retries(3);
+    ///     elevated_cmd.on_auth_failure(|attempts, reason| {
+    ///         if *reason == ElevationError::AuthDismissed {
+    ///             println!("admin rights are required to continue");
+    ///         }
+    ///         if attempts < 2 { RetryDecision::Retry } else { RetryDecision::GiveUp }
+    ///     });
+    ///     let output = elevated_cmd.output();
+    /// }
+    /// ```
+    pub fn on_auth_failure<F>(&mut self, handler: F) -> &mut Self
+    where
+        F: FnMut(usize, &ElevationError) -> RetryDecision + Send + 'static,
+    {
+        self.on_auth_failure = Some(Mutex::new(Box::new(handler)));
+        self
+    }
+
+    // Repeat `attempt` as long as it fails to authenticate and retrying is allowed
+    pub(crate) fn retry_auth<T>(&self, mut attempt: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut failures = 0;
+        loop {
+            let e = match attempt() {
+                Ok(value) => return Ok(value),
+                Err(e) => e,
+            };
+            let reason = match e.downcast_ref::<ElevationError>() {
+                Some(reason @ (ElevationError::AuthDismissed | ElevationError::AuthDenied)) => reason,
+                _ => return Err(e),
+            };
+            failures += 1;
+            if failures > self.auth_retries {
+                return Err(e);
+            }
+            if let Some(ref handler) = self.on_auth_failure {
+                let mut handler = handler.lock().unwrap_or_else(PoisonError::into_inner);
+                if handler(failures, reason) == RetryDecision::GiveUp {
+                    return Err(e);
+                }
+            }
+            log::debug!("Authentication failed ({}), retrying", reason);
+        }
+    }
+}
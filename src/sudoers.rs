@@ -0,0 +1,374 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Luis Liu. All rights reserved.
+ *  Licensed under the MIT License. See License in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+//! Generate `/etc/sudoers.d` drop-ins letting a user or group run an exact command
+//! through `sudo` without a password
+use crate::install::{install_file, uninstall_file, Staging};
+use crate::Command;
+use anyhow::{anyhow, bail, Result};
+use std::env;
+use std::ffi::{CStr, OsStr, OsString};
+use std::fmt::Write;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{self, Path, PathBuf};
+use std::process::Command as StdCommand;
+
+/// The directory sudo includes drop-in files from
+pub const SUDOERS_DIR: &str = "/etc/sudoers.d";
+
+// Where `visudo` is looked for when it is not in `PATH`
+const VISUDO_PATHS: [&str; 2] = ["/usr/sbin/visudo", "/sbin/visudo"];
+
+/// The principal a rule applies to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Principal {
+    User(String),
+    Group(String),
+}
+
+/// A sudoers entry allowing `principal` to run exactly one program with fixed arguments
+///
+/// # Examples
+///
+/// ```no_run
+/// use elevated_command::Command;
+/// use elevated_command::sudoers::SudoersRule;
+/// use std::process::Command as StdCommand;
+///
+/// fn main() {
+///     let mut cmd = StdCommand::new("systemctl");
+///     cmd.arg("restart").arg("my-service");
+///     let elevated_cmd = Command::new(cmd);
+///
+///     let mut rule = SudoersRule::from_command("my-app", &elevated_cmd).unwrap();
+///     rule.group("devops");
+///     rule.install().unwrap();
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct SudoersRule {
+    name: String,
+    principal: Principal,
+    runas: String,
+    program: PathBuf,
+    args: Vec<String>,
+    env_keep: Vec<String>,
+}
+
+impl SudoersRule {
+    /// Constructs a rule for the program, arguments and environment variable names of `cmd`,
+    /// to be installed as `SUDOERS_DIR/<name>`
+    ///
+    /// The program is resolved against `PATH` when it is not a path already,
+    /// the rule applies to the current user and runs the command as root by default
+    pub fn from_command(name: &str, cmd: &Command) -> Result<Self> {
+        let cmd = cmd.get_ref();
+        let program = resolve_program(Path::new(cmd.get_program()))?;
+        let args = cmd
+            .get_args()
+            .map(|a| a.to_str().map(str::to_string).ok_or(anyhow!("invalid argument")))
+            .collect::<Result<Vec<String>>>()?;
+        let env_keep = cmd
+            .get_envs()
+            .filter(|(_, v)| v.is_some())
+            .map(|(k, _)| k.to_str().map(str::to_string).ok_or(anyhow!("invalid key")))
+            .collect::<Result<Vec<String>>>()?;
+
+        Ok(Self {
+            name: name.to_string(),
+            principal: Principal::User(current_user()?),
+            runas: "root".to_string(),
+            program,
+            args,
+            env_keep,
+        })
+    }
+
+    /// Apply the rule to the given user instead
+    pub fn user(&mut self, user: &str) -> &mut Self {
+        self.principal = Principal::User(user.to_string());
+        self
+    }
+
+    /// Apply the rule to the members of the given group instead
+    pub fn group(&mut self, group: &str) -> &mut Self {
+        self.principal = Principal::Group(group.to_string());
+        self
+    }
+
+    /// Set the user the command runs as. Defaults to `root`
+    pub fn runas(&mut self, user: &str) -> &mut Self {
+        self.runas = user.to_string();
+        self
+    }
+
+    /// Keep the given environment variable from the invoking user's environment
+    pub fn env_keep(&mut self, name: &str) -> &mut Self {
+        self.env_keep.push(name.to_string());
+        self
+    }
+
+    /// The path the rule is installed to
+    pub fn path(&self) -> PathBuf {
+        PathBuf::from(SUDOERS_DIR).join(&self.name)
+    }
+
+    /// Generate the content of the drop-in file
+    pub fn render(&self) -> Result<String> {
+        // sudo silently ignores drop-ins whose name contains a dot or ends with `~`
+        if self.name.is_empty() || !self.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            bail!("invalid sudoers file name: {:?}", self.name);
+        }
+        let principal = match self.principal {
+            Principal::User(ref user) => escape_name(user)?,
+            Principal::Group(ref group) => format!("%{}", escape_name(group)?),
+        };
+        let runas = escape_name(&self.runas)?;
+        let program = self.program.to_str().ok_or(anyhow!("invalid program"))?;
+        if !self.program.is_absolute() {
+            bail!("program must be an absolute path: {:?}", program);
+        }
+        let program = escape_arg(program)?;
+
+        // without any argument sudo would allow every argument, `""` allows none
+        let args = if self.args.is_empty() {
+            "\"\"".to_string()
+        } else {
+            self.args.iter().map(|a| escape_arg(a)).collect::<Result<Vec<String>>>()?.join(" ")
+        };
+
+        let mut sudoers = String::new();
+        writeln!(sudoers, "# Generated by elevated-command, do not edit")?;
+        if !self.env_keep.is_empty() {
+            for name in self.env_keep.iter() {
+                if name.is_empty()
+                    || name.starts_with(|c: char| c.is_ascii_digit())
+                    || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                {
+                    bail!("invalid environment variable name: {:?}", name);
+                }
+            }
+            writeln!(sudoers, "Defaults!{} env_keep += \"{}\"", program, self.env_keep.join(" "))?;
+        }
+        writeln!(sudoers, "{} ALL=({}) NOPASSWD: {} {}", principal, runas, program, args)?;
+        Ok(sudoers)
+    }
+
+    /// Check the generated file with `visudo -cf`, without elevation
+    ///
+    /// Returns `false` if `visudo` was not found, the rule could not be checked then
+    pub fn validate(&self) -> Result<bool> {
+        let sudoers = self.render()?;
+        let visudo = match find_visudo() {
+            Some(visudo) => visudo,
+            None => return Ok(false),
+        };
+        let staging = Staging::new()?;
+        let path = staging.file(&self.name, sudoers.as_bytes())?;
+        let output = StdCommand::new(visudo).arg("-cf").arg(&path).output();
+        drop(staging);
+
+        let output = output?;
+        if !output.status.success() {
+            bail!(
+                "visudo rejected the rule: {}{}",
+                String::from_utf8_lossy(&output.stdout).trim_end(),
+                String::from_utf8_lossy(&output.stderr).trim_end(),
+            );
+        }
+        Ok(true)
+    }
+
+    /// Validate the rule and install it, prompting for elevation
+    ///
+    /// The file is checked again with `visudo -cf` as root before it is moved into place,
+    /// so a broken rule never becomes active, even when `visudo` is not available to the
+    /// current user
+    pub fn install(&self) -> Result<()> {
+        self.validate()?;
+        let sudoers = self.render()?;
+        install_file(sudoers.as_bytes(), &self.path(), "0440", Some("visudo"))
+    }
+
+    /// Remove the installed rule, prompting for elevation
+    pub fn uninstall(&self) -> Result<()> {
+        uninstall_file(&self.path())
+    }
+}
+
+// Make `program` absolute the way the shell finds it. Symlinks are kept, as sudo
+// matches the name of the command before comparing the files, and pkexec shows it
+pub(crate) fn resolve_program(program: &Path) -> Result<PathBuf> {
+    if program.components().count() > 1 {
+        return Ok(path::absolute(program)?);
+    }
+    let paths = env::var_os("PATH").ok_or(anyhow!("PATH is not set"))?;
+    for dir in env::split_paths(&paths) {
+        let candidate = dir.join(program);
+        if let Ok(metadata) = candidate.metadata() {
+            if metadata.is_file() && metadata.permissions().mode() & 0o111 != 0 {
+                return Ok(path::absolute(candidate)?);
+            }
+        }
+    }
+    bail!("{} not found in PATH", program.display())
+}
+
+// The name of the current user in the password database, `USER` may be unset or
+// name someone else
+fn current_user() -> Result<String> {
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let mut buffer = vec![0 as libc::c_char; 1024];
+    loop {
+        let err = unsafe {
+            libc::getpwuid_r(libc::getuid(), &mut pwd, buffer.as_mut_ptr(), buffer.len(), &mut result)
+        };
+        match err {
+            0 => break,
+            libc::ERANGE => buffer.resize(buffer.len() * 2, 0),
+            err => return Err(io::Error::from_raw_os_error(err).into()),
+        }
+    }
+    if result.is_null() {
+        bail!("the current user is not in the password database");
+    }
+    let name = unsafe { CStr::from_ptr(pwd.pw_name) };
+    Ok(name.to_str()?.to_string())
+}
+
+// `visudo` lives in an `sbin` directory, which is not in the `PATH` of regular users
+// on some distributions like Debian
+fn find_visudo() -> Option<PathBuf> {
+    resolve_program(Path::new("visudo")).ok().or_else(|| {
+        VISUDO_PATHS
+            .iter()
+            .map(PathBuf::from)
+            .find(|path| path.is_file())
+    })
+}
+
+// The `K=V` argument of sudo setting a variable of the program. It goes before `--`,
+// sudo takes anything after it as the command. With `env_reset`, only the variables
+// in `env_keep` are allowed, like those of a rule built by `SudoersRule::from_command`
+pub(crate) fn env_arg(key: &OsStr, value: &OsStr) -> OsString {
+    let mut arg = key.to_os_string();
+    arg.push("=");
+    arg.push(value);
+    arg
+}
+
+fn escape_name(name: &str) -> Result<String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.') {
+        bail!("invalid user or group name: {:?}", name);
+    }
+    Ok(name.to_string())
+}
+
+fn escape_arg(arg: &str) -> Result<String> {
+    if arg.is_empty() {
+        bail!("empty arguments cannot be expressed in sudoers");
+    }
+    let mut escaped = String::new();
+    for c in arg.chars() {
+        match c {
+            '\n' | '\r' | '\0' => bail!("invalid character in argument: {:?}", arg),
+            '\\' | ',' | ':' | '=' | ' ' | '\t' | '"' | '#' | '*' | '?' | '[' | ']' | '!' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    Ok(escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir, remove_dir_all};
+    use std::os::unix::fs::symlink;
+
+    fn rule(program: &str, args: &[&str]) -> SudoersRule {
+        SudoersRule {
+            name: "my-app".to_string(),
+            principal: Principal::User("alice".to_string()),
+            runas: "root".to_string(),
+            program: PathBuf::from(program),
+            args: args.iter().map(|a| a.to_string()).collect(),
+            env_keep: vec![],
+        }
+    }
+
+    #[test]
+    fn escape_arg_escapes_special_characters() {
+        assert_eq!(escape_arg("restart").unwrap(), "restart");
+        assert_eq!(escape_arg("a b,c:d=e").unwrap(), r"a\ b\,c\:d\=e");
+        assert_eq!(escape_arg(r##"\"#*?[]!"##).unwrap(), r##"\\\"\#\*\?\[\]\!"##);
+        assert_eq!(escape_arg("a\tb").unwrap(), "a\\\tb");
+    }
+
+    #[test]
+    fn escape_arg_rejects_what_sudoers_cannot_express() {
+        assert!(escape_arg("").is_err());
+        assert!(escape_arg("a\nb").is_err());
+        assert!(escape_arg("a\rb").is_err());
+        assert!(escape_arg("a\0b").is_err());
+    }
+
+    #[test]
+    fn render_generates_the_rule() {
+        let mut rule = rule("/usr/bin/systemctl", &["restart", "my service"]);
+        rule.group("devops").runas("svc").env_keep("LANG").env_keep("MY_VAR");
+        assert_eq!(
+            rule.render().unwrap(),
+            "# Generated by elevated-command, do not edit\n\
+             Defaults!/usr/bin/systemctl env_keep += \"LANG MY_VAR\"\n\
+             %devops ALL=(svc) NOPASSWD: /usr/bin/systemctl restart my\\ service\n"
+        );
+    }
+
+    #[test]
+    fn render_allows_no_argument_without_arguments() {
+        assert_eq!(
+            rule("/usr/bin/true", &[]).render().unwrap(),
+            "# Generated by elevated-command, do not edit\nalice ALL=(root) NOPASSWD: /usr/bin/true \"\"\n"
+        );
+    }
+
+    #[test]
+    fn render_rejects_unsafe_values() {
+        assert!(rule("relative/true", &[]).render().is_err());
+        assert!(rule("/usr/bin/true", &[""]).render().is_err());
+        assert!(rule("/usr/bin/true", &[]).user("alice ALL").render().is_err());
+        assert!(rule("/usr/bin/true", &[]).env_keep("A B").render().is_err());
+        assert!(rule("/usr/bin/true", &[]).env_keep("1A").render().is_err());
+
+        let mut rule = rule("/usr/bin/true", &[]);
+        // sudo would ignore a drop-in named like this
+        rule.name = "my.app".to_string();
+        assert!(rule.render().is_err());
+    }
+
+    #[test]
+    fn from_command_applies_to_the_current_user() {
+        let id = StdCommand::new("id").arg("-un").output().unwrap();
+        let user = String::from_utf8(id.stdout).unwrap();
+        let rule = SudoersRule::from_command("my-app", &Command::new(StdCommand::new("/bin/true"))).unwrap();
+        assert_eq!(rule.principal, Principal::User(user.trim_end().to_string()));
+    }
+
+    #[test]
+    fn resolve_program_keeps_symlinks() {
+        let dir = env::temp_dir().join(format!("elevated_cmd_test_{}", std::process::id()));
+        create_dir(&dir).unwrap();
+        let link = dir.join("shell");
+        symlink("/bin/sh", &link).unwrap();
+        let resolved = resolve_program(&link);
+        remove_dir_all(&dir).unwrap();
+        assert_eq!(resolved.unwrap(), link);
+    }
+}
@@ -0,0 +1,155 @@
+/*---------------------------------------------------------------------------------------------
+ *  Copyright (c) Luis Liu. All rights reserved.
+ *  Licensed under the MIT License. See License in the project root for license information.
+ *--------------------------------------------------------------------------------------------*/
+
+use crate::batch::Script;
+use crate::Command;
+use anyhow::Result;
+use std::process::Output;
+
+/// Several commands executed in order under a single elevation, undoing the completed
+/// ones if a command fails
+///
+/// Every step can register a rollback command. When a step exits unsuccessfully, the
+/// rollbacks of the steps completed before it are executed in reverse order, within
+/// the same elevated session. The transaction is shown to the user like its first step,
+/// and like a `Batch` its steps cannot read any input nor stream their output
+///
+/// # Examples
+///
+/// ```no_run
+/// use elevated_command::{Command, Transaction};
+/// use std::process::Command as StdCommand;
+///
+/// fn main() {
+///     let mut add = StdCommand::new("useradd");
+///     add.arg("service");
+///     let mut del = StdCommand::new("userdel");
+///     del.arg("service");
+///     let mut enable = StdCommand::new("systemctl");
+///     enable.args(["enable", "--now", "service"]);
+///
+///     let mut transaction = Transaction::new();
+///     transaction.step_with_rollback(Command::new(add), Command::new(del));
+///     transaction.step(Command::new(enable));
+///
+///     let report = transaction.run().unwrap();
+///     if !report.committed() {
+///         println!("applied: {:?}", report.applied());
+///         println!("rolled back: {:?}", report.rolled_back());
+///     }
+/// }
+/// ```
+#[derive(Default)]
+pub struct Transaction {
+    steps: Vec<(Command, Option<Command>)>,
+}
+
+/// What a `Transaction` applied and rolled back
+#[derive(Debug, Clone)]
+pub struct TransactionReport {
+    /// The output of every executed step, in order
+    pub steps: Vec<Output>,
+    /// The step which exited unsuccessfully, if any
+    pub failed: Option<usize>,
+    /// The output of every executed rollback in the order they ran, with the step it undoes
+    pub rollbacks: Vec<(usize, Output)>,
+}
+
+impl Transaction {
+    /// Constructs an empty transaction
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a step which is not undone if a later step fails
+    pub fn step(&mut self, cmd: Command) -> &mut Self {
+        self.steps.push((cmd, None));
+        self
+    }
+
+    /// Add a step undone by `rollback` if a later step fails
+    pub fn step_with_rollback(&mut self, cmd: Command, rollback: Command) -> &mut Self {
+        self.steps.push((cmd, Some(rollback)));
+        self
+    }
+
+    /// Execute the steps with escalated privileges, rolling back on failure
+    ///
+    /// Fails only if the commands could not be executed at all, a failing step
+    /// is reported in the `TransactionReport`
+    pub fn run(&self) -> Result<TransactionReport> {
+        let mut report = TransactionReport {
+            steps: vec![],
+            failed: None,
+            rollbacks: vec![],
+        };
+        let first = match self.steps.first() {
+            Some((first, _)) => first,
+            None => return Ok(report),
+        };
+
+        for (cmd, rollback) in self.steps.iter() {
+            Script::check(cmd)?;
+            if let Some(rollback) = rollback {
+                Script::check(rollback)?;
+            }
+        }
+        let steps: Vec<_> = self.steps.iter().map(|(cmd, _)| &cmd.cmd).collect();
+        let rollbacks: Vec<_> = self
+            .steps
+            .iter()
+            .map(|(_, rollback)| rollback.as_ref().map(|cmd| &cmd.cmd))
+            .collect();
+        let script = Script::transaction(&steps, &rollbacks)?;
+        script.execute(first)?;
+
+        for step in 0..self.steps.len() {
+            match script.step_output(step)? {
+                Some(output) => {
+                    let success = output.status.success();
+                    report.steps.push(output);
+                    if !success {
+                        report.failed = Some(step);
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+        if let Some(failed) = report.failed {
+            for step in (0..failed).rev() {
+                if let Some(output) = script.rollback_output(step)? {
+                    report.rollbacks.push((step, output));
+                }
+            }
+        }
+        Ok(report)
+    }
+}
+
+impl TransactionReport {
+    /// Whether every step succeeded
+    pub fn committed(&self) -> bool {
+        self.failed.is_none()
+    }
+
+    /// The steps whose changes remain: those which succeeded and were not
+    /// successfully rolled back
+    pub fn applied(&self) -> Vec<usize> {
+        let rolled_back = self.rolled_back();
+        (0..self.steps.len())
+            .filter(|&step| self.steps[step].status.success() && !rolled_back.contains(&step))
+            .collect()
+    }
+
+    /// The steps successfully rolled back, in the order they were undone
+    pub fn rolled_back(&self) -> Vec<usize> {
+        self.rollbacks
+            .iter()
+            .filter(|(_, output)| output.status.success())
+            .map(|&(step, _)| step)
+            .collect()
+    }
+}
@@ -7,26 +7,32 @@
  *  SEE_MASK_NOCLOSEPROCESS and WaitForSingleObject, ensuring reliable synchronization.
  *--------------------------------------------------------------------------------------------*/
 
+use crate::backpressure::{event_channel, EventSender};
+use crate::AuthStatus;
+use crate::Backend;
+use crate::BackendAvailability;
 use crate::Command;
 use crate::CommandChild;
 use crate::CommandEvent;
+use crate::ElevatedOutput;
+use crate::ElevationError;
 use anyhow::Result;
 use std::env;
 use std::fs::{self, File};
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::mem;
 use std::os::windows::process::ExitStatusExt;
 use std::path::PathBuf;
 use std::process::{Output, ExitStatus};
-use std::sync::mpsc::{channel, Sender, Receiver};
+use std::sync::mpsc::Receiver;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use winapi::shared::minwindef::{DWORD, LPVOID};
 use winapi::um::processthreadsapi::{GetCurrentProcess, OpenProcessToken};
 use winapi::um::securitybaseapi::GetTokenInformation;
 use winapi::um::winnt::{HANDLE, TokenElevation, TOKEN_ELEVATION, TOKEN_QUERY};
 use windows::core::{HSTRING, PCWSTR, w};
-use windows::Win32::Foundation::HWND;
+use windows::Win32::Foundation::{ERROR_CANCELLED, HWND};
 use windows::Win32::UI::Shell::{ShellExecuteW, ShellExecuteExW, SHELLEXECUTEINFOW};
 use windows::Win32::UI::WindowsAndMessaging::SW_HIDE;
 use windows::Win32::UI::Shell::{SEE_MASK_NOASYNC, SEE_MASK_NOCLOSEPROCESS};
@@ -97,6 +103,53 @@ impl Command {
     /// }
     /// ```
     pub fn output(&self) -> Result<Output> {
+        self.retry_auth(|| self.output_once())
+    }
+
+    /// Execute the command with escalated privileges like `Command::output`, also
+    /// reporting how it was elevated and how long it took
+    ///
+    /// The consent dialog does not tell when the user answered it, so `auth_wait`
+    /// is unknown and `run_duration` includes the authentication
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::Command;
+    /// use std::process::Command as StdCommand;
+    ///
+    /// fn main() {
+    ///     let cmd = StdCommand::new("path to the application");
+    ///     let elevated_cmd = Command::new(cmd);
+    ///     let output = elevated_cmd.output_details().unwrap();
+    ///     println!("ran for {:?} with {:?}", output.run_duration, output.backend);
+    /// }
+    /// ```
+    pub fn output_details(&self) -> Result<ElevatedOutput> {
+        let start = Instant::now();
+        let output = self.output()?;
+        let run_duration = start.elapsed();
+        // UAC elevates the current user, without asking when elevated already
+        let auth_wait = if Command::is_elevated() { Some(Duration::ZERO) } else { None };
+        Ok(ElevatedOutput {
+            status: output.status,
+            stdout: output.stdout,
+            stderr: output.stderr,
+            auth_wait,
+            run_duration,
+            backend: Backend::Uac,
+            program: PathBuf::from(self.cmd.get_program()),
+            user: env::var("USERNAME").unwrap_or_default(),
+        })
+    }
+
+    fn output_once(&self) -> Result<Output> {
+        // UAC asks for consent on every elevation
+        if self.non_interactive && !Command::is_elevated() {
+            return Err(ElevationError::InteractionRequired.into());
+        }
+        let _turn = self.wait_turn()?;
+
         // Helper function to escape Windows command-line arguments
         fn windows_escape_arg(arg: &str) -> String {
             if arg.is_empty() {
@@ -211,6 +264,12 @@ impl Command {
             let _ = fs::remove_file(&stdout_file);
             let _ = fs::remove_file(&stderr_file);
             let _ = fs::remove_file(&exitcode_file);
+            // the user declined the UAC prompt
+            if let Err(ref e) = success {
+                if e.code() == ERROR_CANCELLED.to_hresult() {
+                    return Err(ElevationError::AuthDismissed.into());
+                }
+            }
             return Err(anyhow::anyhow!("Failed to execute elevated command"));
         }
 
@@ -253,6 +312,95 @@ impl Command {
         })
     }
 
+    /// Check whether executing the command would show the UAC dialog,
+    /// without prompting the user
+    /// 
+    /// UAC asks for consent on every elevation, so this reports `AuthStatus::Authorized`
+    /// only when the current process is elevated already
+    /// 
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::{AuthStatus, Command};
+    /// use std::process::Command as StdCommand;
+    ///
+    /// fn main() {
+    ///     let cmd = StdCommand::new("path to the application");
+    ///     let elevated_cmd = Command::new(cmd);
+    ///     if elevated_cmd.authorization_status().unwrap() == AuthStatus::ChallengeRequired {
+    ///         println!("this requires admin");
+    ///     }
+    /// }
+    /// ```
+    pub fn authorization_status(&self) -> Result<AuthStatus> {
+        if Command::is_elevated() {
+            Ok(AuthStatus::Authorized)
+        } else {
+            Ok(AuthStatus::ChallengeRequired)
+        }
+    }
+
+    /// List the ways to escalate privileges available on this system, without prompting the user
+    /// 
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::Command;
+    ///
+    /// fn main() {
+    ///     for availability in Command::can_elevate() {
+    ///         println!("{:?} graphical: {}", availability.backend, availability.graphical_agent);
+    ///     }
+    /// }
+    /// ```
+    pub fn can_elevate() -> Vec<BackendAvailability> {
+        let mut backends = vec![];
+        if Command::is_elevated() {
+            backends.push(BackendAvailability {
+                backend: Backend::Direct,
+                version: None,
+                graphical_agent: false,
+                text_agent: false,
+                non_interactive: true,
+            });
+        }
+        // UAC asks for consent on every elevation
+        backends.push(BackendAvailability {
+            backend: Backend::Uac,
+            version: None,
+            graphical_agent: true,
+            text_agent: false,
+            non_interactive: false,
+        });
+        backends
+    }
+
+    /// Execute the command with escalated privileges, waiting for it to finish and
+    /// returning its exit status
+    /// 
+    /// The elevated process is not attached to the terminal of the current program,
+    /// its output is written to the terminal once it exits
+    /// 
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use elevated_command::Command;
+    /// use std::process::Command as StdCommand;
+    ///
+    /// fn main() {
+    ///     let cmd = StdCommand::new("path to the application");
+    ///     let elevated_cmd = Command::new(cmd);
+    ///     let status = elevated_cmd.status().unwrap();
+    ///     println!("exited with {}", status);
+    /// }
+    /// ```
+    pub fn status(&self) -> Result<ExitStatus> {
+        let output = self.output()?;
+        std::io::stdout().write_all(&output.stdout)?;
+        std::io::stderr().write_all(&output.stderr)?;
+        Ok(output.status)
+    }
+
     /// Execute with escalated privileges and stream output in real-time
     /// 
     /// Returns a channel receiver for CommandEvent messages and a CommandChild handle
@@ -277,7 +425,7 @@ impl Command {
     ///             CommandEvent::Stderr(data) => {
     ///                 eprintln!("ERR: {}", String::from_utf8_lossy(&data));
     ///             }
-    ///             CommandEvent::Terminated { code } => {
+    ///             CommandEvent::Terminated { code, .. } => {
     ///                 println!("Process exited with code: {:?}", code);
     ///                 break;
     ///             }
@@ -285,11 +433,28 @@ impl Command {
     ///                 eprintln!("Error: {}", err);
     ///                 break;
     ///             }
+    ///             CommandEvent::AuthRequested => {
+    ///                 println!("Waiting for authentication");
+    ///             }
+    ///             _ => {}
     ///         }
     ///     }
     /// }
     /// ```
     pub fn spawn(self) -> Result<(Receiver<CommandEvent>, CommandChild)> {
+        let (tx, rx) = event_channel(self.event_capacity, self.replay_buffer);
+        let child = self.spawn_events(tx)?;
+        Ok((rx, child))
+    }
+
+    // Spawn the command, reporting its events through `tx`
+    pub(crate) fn spawn_events(self, tx: EventSender) -> Result<CommandChild> {
+        // UAC asks for consent on every elevation
+        if self.non_interactive && !Command::is_elevated() {
+            return Err(ElevationError::InteractionRequired.into());
+        }
+        let turn = self.wait_turn()?;
+
         // Helper function to escape Windows command-line arguments
         fn windows_escape_arg(arg: &str) -> String {
             if arg.is_empty() {
@@ -395,8 +560,8 @@ impl Command {
             };
         });
 
-        // Create channel for events
-        let (tx, rx) = channel();
+        let _ = tx.send(CommandEvent::AuthRequested);
+        let broadcast = tx.broadcast();
 
         // Clone paths for the monitor thread
         let stdout_path = stdout_file.clone();
@@ -407,20 +572,19 @@ impl Command {
         // Spawn thread to monitor output files
         thread::spawn(move || {
             monitor_output_files_windows(tx, stdout_path, stderr_path, exitcode_path, wrapper_path);
+            drop(turn);
         });
 
-        Ok((
-            rx,
-            CommandChild {
-                _output_dir: temp_dir,
-            },
-        ))
+        Ok(CommandChild {
+            _output_dir: temp_dir,
+            broadcast,
+        })
     }
 }
 
 // Monitor output files and send events through the channel (Windows version)
 fn monitor_output_files_windows(
-    tx: Sender<CommandEvent>,
+    tx: EventSender,
     stdout_path: PathBuf,
     stderr_path: PathBuf,
     exitcode_path: PathBuf,
@@ -455,6 +619,7 @@ fn monitor_output_files_windows(
         // Check if output files exist (means process started)
         if !files_appeared && (stdout_path.exists() || stderr_path.exists()) {
             files_appeared = true;
+            let _ = tx.send(CommandEvent::AuthGranted);
         }
 
         // Try to read new stdout data
@@ -547,7 +712,7 @@ fn monitor_output_files_windows(
                         }
 
                         // Send termination event
-                        let _ = tx.send(CommandEvent::Terminated { code: exit_code });
+                        let _ = tx.send(CommandEvent::Terminated { code: exit_code, signal: None, dropped: 0 });
                         
                         // Clean up files
                         let _ = fs::remove_file(&stdout_path);
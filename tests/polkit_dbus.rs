@@ -0,0 +1,186 @@
+// Exercise the `polkit-dbus` backend against a stand-in polkit authority
+// served on a private bus, so no real polkit daemon or agent is involved
+//
+//     cargo test --features polkit-dbus --test polkit_dbus
+//
+// Skipped when `dbus-daemon` is not in PATH
+#![cfg(target_os = "linux")]
+
+use elevated_command::{AuthStatus, Cancellation, Command, ElevationError};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, ErrorKind};
+use std::process::{Child, Command as StdCommand, Stdio};
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker};
+use std::thread;
+use std::time::Duration;
+use zbus::zvariant::OwnedValue;
+
+#[derive(Default)]
+struct Cancelled {
+    ids: Vec<String>,
+    wakers: Vec<Waker>,
+}
+
+struct FakeAuthority {
+    cancelled: Arc<Mutex<Cancelled>>,
+}
+
+#[derive(Debug, zbus::DBusError)]
+#[zbus(prefix = "org.freedesktop.PolicyKit1.Error")]
+enum PolkitError {
+    #[zbus(error)]
+    ZBus(zbus::Error),
+    Cancelled(String),
+}
+
+#[zbus::interface(name = "org.freedesktop.PolicyKit1.Authority")]
+impl FakeAuthority {
+    async fn check_authorization(
+        &self,
+        _subject: (String, HashMap<String, OwnedValue>),
+        action_id: String,
+        details: HashMap<String, String>,
+        flags: u32,
+        cancellation_id: String,
+    ) -> Result<(bool, bool, HashMap<String, String>), PolkitError> {
+        let interactive = flags & 1 != 0;
+        let mut reply = HashMap::new();
+        match action_id.as_str() {
+            "com.example.allowed" => {
+                let allowed = details.get("reason").map(String::as_str) == Some("maintenance");
+                Ok((allowed, false, reply))
+            }
+            "com.example.challenge" => Ok((interactive, !interactive, reply)),
+            "com.example.dismissed" => {
+                reply.insert("polkit.dismissed".to_string(), "true".to_string());
+                Ok((false, true, reply))
+            }
+            "com.example.slow" => {
+                let cancelled = self.cancelled.clone();
+                std::future::poll_fn(|cx| {
+                    let mut cancelled = cancelled.lock().unwrap();
+                    if cancelled.ids.contains(&cancellation_id) {
+                        Poll::Ready(())
+                    } else {
+                        cancelled.wakers.push(cx.waker().clone());
+                        Poll::Pending
+                    }
+                })
+                .await;
+                Err(PolkitError::Cancelled("Authorization request was cancelled".to_string()))
+            }
+            _ => Ok((false, false, reply)),
+        }
+    }
+
+    async fn cancel_check_authorization(&self, cancellation_id: String) {
+        let mut cancelled = self.cancelled.lock().unwrap();
+        cancelled.ids.push(cancellation_id);
+        for waker in cancelled.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+// The private bus, stopped when dropped
+struct Bus {
+    daemon: Child,
+    address: String,
+}
+
+impl Bus {
+    fn start() -> Option<Self> {
+        let mut daemon = match StdCommand::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address=1"])
+            .stdout(Stdio::piped())
+            .spawn()
+        {
+            Ok(daemon) => daemon,
+            Err(e) if e.kind() == ErrorKind::NotFound => return None,
+            Err(e) => panic!("failed to start dbus-daemon: {}", e),
+        };
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+        Some(Self {
+            daemon,
+            address: address.trim().to_string(),
+        })
+    }
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
+
+// The backend keeps its connection to the system bus for the whole process, so all
+// checks share a single test pointing it to the private bus
+#[test]
+fn stand_in_authority() {
+    let bus = match Bus::start() {
+        Some(bus) => bus,
+        None => {
+            eprintln!("dbus-daemon not found, skipping");
+            return;
+        }
+    };
+
+    let authority = FakeAuthority {
+        cancelled: Arc::new(Mutex::new(Cancelled::default())),
+    };
+    let _server = zbus::blocking::connection::Builder::address(bus.address.as_str())
+        .unwrap()
+        .name("org.freedesktop.PolicyKit1")
+        .unwrap()
+        .serve_at("/org/freedesktop/PolicyKit1/Authority", authority)
+        .unwrap()
+        .build()
+        .unwrap();
+    std::env::set_var("DBUS_SYSTEM_BUS_ADDRESS", &bus.address);
+
+    // authorized with details
+    let mut cmd = elevated("com.example.allowed");
+    cmd.detail("reason", "maintenance");
+    assert!(cmd.authorize().is_ok());
+
+    // a missing detail is denied
+    let cmd = elevated("com.example.allowed");
+    assert_error(cmd.authorize(), ElevationError::AuthDenied);
+
+    // a dismissed dialog
+    let cmd = elevated("com.example.dismissed");
+    assert_error(cmd.authorize(), ElevationError::AuthDismissed);
+
+    // the status without interaction, root is always authorized without asking polkit
+    if !Command::is_elevated() {
+        let cmd = elevated("com.example.challenge");
+        assert_eq!(cmd.authorization_status().ok(), Some(AuthStatus::ChallengeRequired));
+        let cmd = elevated("com.example.unknown");
+        assert_eq!(cmd.authorization_status().ok(), Some(AuthStatus::Denied));
+    }
+
+    // a cancelled authorization
+    let cancellation = Cancellation::new();
+    let mut cmd = elevated("com.example.slow");
+    cmd.cancellation(cancellation.clone());
+    let handle = thread::spawn(move || cmd.authorize());
+    thread::sleep(Duration::from_millis(500));
+    cancellation.cancel().unwrap();
+    assert_error(handle.join().unwrap(), ElevationError::Cancelled);
+}
+
+fn elevated(action_id: &str) -> Command {
+    let mut cmd = Command::new(StdCommand::new("true"));
+    cmd.action_id(action_id);
+    cmd
+}
+
+fn assert_error(result: anyhow::Result<()>, expected: ElevationError) {
+    let err = result.expect_err("the authorization should fail");
+    assert_eq!(err.downcast_ref::<ElevationError>(), Some(&expected));
+}